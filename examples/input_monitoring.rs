@@ -0,0 +1,34 @@
+use audio_mixer::*;
+use std::{thread::sleep, time::Duration};
+
+// This example plays the default input device (e.g. your microphone) through
+// the default output device. Use headphones to avoid feedback!
+//
+// AudioInput is an iterator like any other source so it needs converting to the
+// output's channels and sample rate first. Here it is also run through a
+// LowPassFilter to show that it works with the existing adapters.
+//
+// The input never finishes by itself so this stops it after ten seconds by
+// removing it via the handle that AudioMixer::add returns.
+
+fn main() {
+  let input = AudioInput::for_default_device().unwrap();
+  let mixer = AudioMixer::for_default_device().unwrap();
+
+  let in_channels = input.channels();
+  let out_channels = mixer.channels();
+
+  let in_rate = input.sample_rate();
+  let out_rate = mixer.sample_rate();
+
+  let source1 = IntoSampleRate::new(in_rate, out_rate, in_channels, input);
+  let source2 = IntoChannels::new(in_channels, out_channels, source1);
+
+  let coefficients = LowPassCoefficients::new([out_rate].into_iter(), 5_000);
+  let source3 = LowPassFilter::new(3_000, out_channels, out_rate, source2, coefficients.clone_arc());
+
+  let handle = mixer.add(source3);
+
+  sleep(Duration::from_secs(10));
+  handle.remove();
+}
@@ -18,4 +18,7 @@ fn main() {
 
     // You can build the audio mixer for a specific device with:
     // AudioMixer::for_device(&devices[0]);
+    //
+    // Or move an existing mixer (and everything that's playing) onto it with:
+    // mixer.set_device(&devices[0]);
 }
@@ -0,0 +1,29 @@
+use audio_mixer::*;
+use std::io::Cursor;
+
+// This example plays a wav file on the default output device and mirrors the
+// mix to the last output device in the list at the same time, e.g. so you can
+// hear it on your headphones while it also goes to a loopback device.
+//
+// The mirror converts the mix to the other device's sample rate and channels
+// and corrects for the devices' clocks drifting apart. It stops when dropped.
+
+fn main() {
+  let cursor = Cursor::new(include_bytes!("./wav_file.wav"));
+  let decoder = WavDecoder::new(cursor).unwrap();
+  let mixer = AudioMixer::for_default_device().unwrap();
+
+  let device = AudioMixer::output_devices().pop().unwrap();
+  let mirror = mixer.add_mirror(device, MirrorOptions::default()).unwrap();
+
+  println!("Mirroring to a device with {} channels at {}Hz", mirror.channels(), mirror.sample_rate());
+
+  let in_channels = decoder.channels();
+  let out_channels = mixer.channels();
+
+  let source1 = IntoSampleRate::new(decoder.sample_rate(), mixer.sample_rate(), in_channels, decoder);
+  let source2 = IntoChannels::new(in_channels, out_channels, source1);
+
+  mixer.add(source2);
+  mixer.wait();
+}
@@ -0,0 +1,52 @@
+use audio_mixer::*;
+use std::{io::Cursor, thread::sleep, time::Duration};
+
+// This example routes sources into buses so that they can be controlled as a
+// group, e.g. to provide separate volume sliders for music and sound effects.
+//
+// Each bus feeds into the output or into another bus that was created before
+// it. Here the music and sfx buses both feed into a master bus. The sfx bus
+// also runs its samples through an effect chain built from the crate's
+// iterators. The chain starts with a BusInput that yields the mixed samples.
+
+fn main() {
+  let cursor = Cursor::new(include_bytes!("./ogg_file.ogg"));
+  let decoder = OggDecoder::new(cursor).unwrap();
+  let mixer = AudioMixer::for_default_device().unwrap();
+
+  let in_channels = decoder.channels();
+  let out_channels = mixer.channels();
+
+  let in_rate = decoder.sample_rate();
+  let out_rate = mixer.sample_rate();
+
+  let source1 = IntoSampleRate::new(in_rate, out_rate, in_channels, decoder);
+  let source2 = IntoChannels::new(in_channels, out_channels, source1);
+  let reusable = ReusableBuffer::new(0, source2.collect());
+
+  let coefficients = LowPassCoefficients::new([out_rate].into_iter(), 20_000);
+
+  let master = mixer.add_bus("master");
+  let music = mixer.add_bus_with("music", Some(&master), |input| input);
+  let sfx = mixer.add_bus_with("sfx", Some(&master), |input| {
+      LowPassFilter::new(500, out_channels, out_rate, input, coefficients)
+  });
+
+  mixer.add_to_bus(&music, reusable.reuse_from(0));
+  sleep(Duration::from_millis(1000));
+
+  println!("Playing the sound again through the muffled sfx bus.");
+  mixer.add_to_bus(&sfx, reusable.reuse_from(0));
+  sleep(Duration::from_millis(1000));
+
+  println!("Turning the music down.");
+  music.set_volume(0.2);
+  sleep(Duration::from_millis(1000));
+
+  println!("Muting everything via the master bus.");
+  master.set_muted(true);
+  sleep(Duration::from_millis(1000));
+
+  master.set_muted(false);
+  mixer.wait();
+}
@@ -0,0 +1,31 @@
+use audio_mixer::{AudioMixer, IntoChannels, IntoSampleRate, WavDecoder};
+use std::io::Cursor;
+use std::time::Duration;
+
+// This example renders a wav file at half pitch into another wav file without
+// using an audio device. This is much faster than real-time and works on
+// machines that don't have a sound card, e.g. in CI.
+//
+// An offline mixer only produces samples when you call one of its render
+// methods. You can also call mixer.render(&mut buffer) to pull blocks of
+// samples on demand, e.g. to compare the mix against expected output in tests.
+
+fn main() {
+  let cursor = Cursor::new(include_bytes!("./wav_file.wav"));
+  let decoder = WavDecoder::new(cursor).unwrap();
+  let mixer = AudioMixer::offline(2, 48000);
+
+  let in_channels = decoder.channels();
+  let out_channels = mixer.channels();
+
+  let in_rate = decoder.sample_rate() / 2; // Render this example at half the pitch.
+  let out_rate = mixer.sample_rate();
+
+  let source1 = IntoSampleRate::new(in_rate, out_rate, in_channels, decoder);
+  let source2 = IntoChannels::new(in_channels, out_channels, source1);
+
+  mixer.add(source2);
+  mixer.render_to_wav_file("haunting.wav", Duration::from_secs(8)).unwrap();
+
+  println!("Written to haunting.wav");
+}
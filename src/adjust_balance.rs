@@ -53,6 +53,12 @@ impl<B: MaybeDynamic<f32>, S: Iterator<Item=f32>> Iterator for AdjustBalance<B,
     }
 }
 
+impl<B: MaybeDynamic<f32>, S: Iterator<Item=f32>> BlockSource for AdjustBalance<B, S> {
+    fn fill_block(&mut self, block: &mut [f32]) -> usize {
+        fill_from_iterator(self, block)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
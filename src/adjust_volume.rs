@@ -18,3 +18,14 @@ impl<V: MaybeDynamic<f32>, S: Iterator<Item=f32>> Iterator for AdjustVolume<V, S
         self.source.next().map(|s| s * self.volume.get())
     }
 }
+
+// The volume is only read once per block when processing blocks.
+impl<V: MaybeDynamic<f32>, S: Iterator<Item=f32> + BlockSource> BlockSource for AdjustVolume<V, S> {
+    fn fill_block(&mut self, block: &mut [f32]) -> usize {
+        let written = self.source.fill_block(block);
+        let volume = self.volume.get();
+
+        block[..written].iter_mut().for_each(|s| *s *= volume);
+        written
+    }
+}
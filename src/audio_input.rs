@@ -0,0 +1,193 @@
+use crate::*;
+use crate::mixer_error::ErrorHandler;
+use crate::ring_buffer::RingBuffer;
+use std::thread::{self, Thread};
+
+// Captures audio from an input device such as a microphone or line-in. It is an
+// iterator of channel-interlaced samples so it can be added to the mixer for
+// monitoring or run through the other adapters first, e.g. IntoSampleRate if
+// the input and output devices don't have the same sample rate.
+//
+// The input stream runs on its own thread and writes into a ring buffer that
+// this reads from. If the buffer fills up, new samples are dropped and if it
+// runs dry, frames of silence are returned. The iterator never finishes so
+// remove it via its SourceHandle when you're done. Stream errors are reported
+// in the same way as the mixer's, i.e. printed to stderr unless handled.
+
+pub struct AudioInput {
+    channels: usize,
+    sample_rate: usize,
+    buffer: Arc<RingBuffer>,
+    silence: usize,
+    channel: usize,
+    stream_thread: Option<(Thread, Arc<AtomicBool>)>,
+    errors: Arc<ErrorHandler>,
+}
+
+const DEFAULT_BUFFER_DURATION: Duration = Duration::from_millis(100);
+
+impl AudioInput {
+    pub fn for_default_device() -> Result<Self, MixerError> {
+        match default_host().default_input_device() {
+            Some(device) => Self::for_device(device),
+            None => Err(MixerError::DeviceNotAvailable),
+        }
+    }
+
+    pub fn for_device(device: Device) -> Result<Self, MixerError> {
+        Self::for_device_with_buffer_duration(device, DEFAULT_BUFFER_DURATION)
+    }
+
+    // A longer buffer is less likely to run dry but adds more latency if the
+    // input runs ahead of whatever is reading from it.
+    pub fn for_device_with_buffer_duration(device: Device, duration: Duration) -> Result<Self, MixerError> {
+        let (sender, receiver) = channel();
+        let closed = Arc::new(AtomicBool::new(false));
+        let closed_clone = closed.clone();
+
+        let errors = Arc::new(ErrorHandler::default());
+        let errors_clone = errors.clone();
+
+        // Streams can't be sent between threads so this one is built and kept
+        // on a thread of its own, which lets AudioInput be sent to the mixer.
+        let handle = thread::spawn(move || match Self::build_stream(&device, duration, errors_clone) {
+            Ok((stream, channels, sample_rate, buffer)) => {
+                let _ = sender.send(Ok((channels, sample_rate, buffer)));
+
+                while !closed_clone.load(Ordering::Relaxed) { thread::park(); }
+                drop(stream);
+            },
+            Err(error) => { let _ = sender.send(Err(error)); },
+        });
+
+        let (channels, sample_rate, buffer) = receiver.recv().map_err(|_| MixerError::StreamThreadPanicked)??;
+        let stream_thread = Some((handle.thread().clone(), closed));
+
+        Ok(Self { channels, sample_rate, buffer, silence: 0, channel: 0, stream_thread, errors })
+    }
+
+    pub fn input_devices() -> Vec<cpal::Device> {
+        let mut input_devices = vec![];
+
+        for host_id in available_hosts() {
+            let host = match host_from_id(host_id) { Ok(h) => h, _ => continue };
+            let devices = match host.devices() { Ok(d) => d, _ => continue };
+
+            for device in devices {
+                let configs = match device.supported_input_configs() { Ok(i) => i, _ => continue };
+                let has_input = configs.into_iter().next().is_some();
+
+                if has_input { input_devices.push(device); }
+            }
+        }
+
+        input_devices
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> usize {
+        self.sample_rate
+    }
+
+    // Errors from the input stream, such as the device being unplugged, are
+    // passed to this function. It is called from the stream's thread.
+    pub fn set_error_handler<F: FnMut(MixerError) + Send + 'static>(&self, function: F) {
+        self.errors.set_function(Some(Box::new(function)));
+    }
+
+    // Returns a channel that receives the input stream's errors. This replaces
+    // the error handler if one was set.
+    pub fn errors(&self) -> Receiver<MixerError> {
+        let (sender, receiver) = channel();
+        self.set_error_handler(move |error| { let _ = sender.send(error); });
+
+        receiver
+    }
+
+    fn build_stream(device: &Device, duration: Duration, errors: Arc<ErrorHandler>) -> Result<(Stream, usize, usize, Arc<RingBuffer>), MixerError> {
+        let config = device.default_input_config()?;
+
+        let channels = config.channels() as usize;
+        let sample_rate = config.sample_rate().0 as usize;
+
+        let frames = (duration.as_secs_f64() * sample_rate as f64).ceil() as usize;
+        let buffer = Arc::new(RingBuffer::new(frames.max(1) * channels));
+
+        let stream = match config.sample_format() {
+            SampleFormat::F32 => Self::build_input_stream::<f32>(device, &config.into(), buffer.clone(), errors),
+            SampleFormat::I16 => Self::build_input_stream::<i16>(device, &config.into(), buffer.clone(), errors),
+            SampleFormat::U16 => Self::build_input_stream::<u16>(device, &config.into(), buffer.clone(), errors),
+        }?;
+
+        stream.play()?;
+        Ok((stream, channels, sample_rate, buffer))
+    }
+
+    fn build_input_stream<S: Sample>(device: &Device, config: &StreamConfig, buffer: Arc<RingBuffer>, errors: Arc<ErrorHandler>) -> Result<Stream, MixerError> {
+        let channels = config.channels as usize;
+
+        let stream = device.build_input_stream::<S, _, _>(config, move |data, _info| {
+            buffer.push_frames(data.iter().map(|s| s.to_f32()), channels);
+        }, move |error| {
+            errors.report(MixerError::Stream(error));
+        })?;
+
+        Ok(stream)
+    }
+}
+
+impl Iterator for AudioInput {
+    type Item = f32;
+
+    // Only start reading a frame if it is all there so that silence is always
+    // returned in whole frames and the channels don't get out of step.
+    fn next(&mut self) -> Option<f32> {
+        if self.silence > 0 {
+            self.silence -= 1;
+            return Some(0.);
+        }
+
+        if self.channel == 0 && self.buffer.len() < self.channels {
+            self.silence = self.channels - 1;
+            return Some(0.);
+        }
+
+        self.channel = (self.channel + 1) % self.channels;
+        Some(self.buffer.pop().unwrap_or(0.))
+    }
+}
+
+impl BlockSource for AudioInput {
+    fn fill_block(&mut self, block: &mut [f32]) -> usize {
+        fill_from_iterator(self, block)
+    }
+}
+
+impl Drop for AudioInput {
+    fn drop(&mut self) {
+        if let Some((thread, closed)) = self.stream_thread.take() {
+            closed.store(true, Ordering::Relaxed);
+            thread.unpark();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_returns_whole_frames_of_silence_when_the_buffer_runs_dry() {
+        let buffer = Arc::new(RingBuffer::new(8));
+        let mut input = AudioInput { channels: 2, sample_rate: 44100, buffer: buffer.clone(), silence: 0, channel: 0, stream_thread: None, errors: Arc::default() };
+
+        buffer.push_frames([1., 2.].into_iter(), 2);
+        assert_eq!(input.by_ref().take(4).collect::<Vec<_>>(), vec![1., 2., 0., 0.]);
+
+        buffer.push_frames([3., 4.].into_iter(), 2);
+        assert_eq!(input.by_ref().take(2).collect::<Vec<_>>(), vec![3., 4.]);
+    }
+}
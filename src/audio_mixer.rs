@@ -1,67 +1,293 @@
 use crate::*;
+use crate::source_handle::SourceState;
+use crate::bus::{BusBuffer, BusNode};
+use crate::device_recovery::{RecoveryCommand, spawn_recovery_thread};
+use crate::master_stage::MasterStage;
+use crate::meter::MeterState;
+use crate::mirror::MirrorTap;
+use crate::mixer_error::ErrorHandler;
+use crate::mixer_event::{event_channel, EventSenders};
+use crate::playback_clock::PlaybackClock;
+use crate::waiting::{Condition, Notifier};
+
+// The mix is processed in blocks of at most this many samples so that buffers
+// can be allocated up front rather than on the audio thread. It is a number of
+// samples rather than frames so the buffers still fit after set_device.
+const MAX_BLOCK_SAMPLES: usize = 8192;
+
+// Each mixer has an id so that it can tell whether a Bus belongs to it.
+static NEXT_MIXER_ID: AtomicUsize = AtomicUsize::new(0);
+
+// The audio thread never waits on a lock. The AudioMixer sends commands to it
+// over a channel which are applied at the start of each callback and the audio
+// thread publishes its status through atomics that the AudioMixer can read.
+//
+// Inner is kept in a mutex so that set_device can take it back from the old
+// stream, even if that stream's device has gone away. Nothing else locks it
+// while a device is playing: controls go through the command channel and the
+// format is only changed once the old stream has been dropped. The callback
+// only ever tries the lock so it can't wait on it, and a panic doesn't stop the
+// mix because the lock is taken back from a poisoned mutex (see lock_inner).
+//
+// The stream is replaced on the recovery thread if its device disappears so
+// the details of the current output are shared with that thread.
 
 pub struct AudioMixer {
+    id: usize,
+    commands: Sender<Command>,
+    status: Arc<Status>,
+    next_id: AtomicUsize,
+    buses: Mutex<Vec<Bus>>,
+    master_gain: DynamicFloat,
+    inner: Arc<Mutex<Inner>>,
+    output: Arc<Mutex<Output>>,
+    stream: Option<Stream>,
+    errors: Arc<ErrorHandler>,
+    recovery_policy: RecoveryPolicy,
+    recovery: Option<Sender<RecoveryCommand>>,
+    notifier: Arc<Notifier>,
+    request: AudioMixerBuilder,
+}
+
+pub(crate) struct Output {
     channels: usize,
     sample_rate: usize,
-    inner: Arc<Mutex<Inner>>,
-    _stream: Stream,
+    config: Option<OutputConfig>,
+    master_levels: MeterLevels,
+    pub generation: usize,
 }
 
-struct Inner {
+pub(crate) struct Inner {
     channels: usize,
     sample_rate: usize,
     sample_count: usize,
-    pending: Vec<Box<dyn Iterator<Item=f32> + Send>>,
-    playing: Vec<Box<dyn Iterator<Item=f32> + Send>>,
+    commands: Receiver<Command>,
+    status: Arc<Status>,
+    pending: Vec<Source>,
+    playing: Vec<Source>,
     recorder: Option<AudioRecorder>,
+    buses: Vec<BusNode>,
+    master: MasterStage,
+    meter: MeterState,
+    events: EventSenders,
+    mirrors: Vec<Arc<MirrorTap>>,
+    errors: Arc<ErrorHandler>,
+    voice_limit: Option<VoiceLimit>,
+    dither: Option<Dither>,
+    block: Vec<f32>,
+    block_len: usize,
+    generation: usize,
+}
+
+pub(crate) struct Source {
+    id: usize,
+    block_source: Box<dyn BlockSource + Send>,
+    state: Arc<SourceState>,
+    bus: Option<usize>,
+    start_frame: Option<usize>,
+    offset: usize,
+    samples_played: usize,
+    priority: i32,
+    level: f32,
+    fade: Option<Fade>,
+}
+
+// A fade out that is applied to a source that was stolen, counted in samples.
+struct Fade {
+    remaining: usize,
+    length: usize,
+}
+
+pub(crate) enum Command {
+    Add(Source),
+    AddBus(BusNode),
+    Remove(usize),
+    StartRecording(AudioRecorder),
+    AddMirror(Arc<MirrorTap>),
+    StopRecording,
+    Subscribe(SyncSender<MixerEvent>),
+}
+
+#[derive(Default)]
+pub(crate) struct Status {
+    active_sources: AtomicUsize,
+    frame_count: AtomicUsize,
+    is_recording: AtomicBool,
+    dropped_events: Arc<AtomicUsize>,
+    clock: PlaybackClock,
 }
 
 impl AudioMixer {
-    pub fn for_default_device() -> Result<Self, DefaultStreamConfigError> {
+    // Returns a builder for choosing the channels, sample rate, sample format or
+    // buffer size of the stream rather than using the device's defaults.
+    pub fn builder() -> AudioMixerBuilder {
+        AudioMixerBuilder::new()
+    }
+
+    pub fn for_default_device() -> Result<Self, MixerError> {
+        Self::for_default_device_with_options(MixerOptions::default())
+    }
+
+    pub fn for_default_device_with_options(options: MixerOptions) -> Result<Self, MixerError> {
         if let Some(device) = default_host().default_output_device() {
-            Self::for_device(&device)
+            Self::for_device_with_options(&device, options)
         } else {
-            Err(DefaultStreamConfigError::DeviceNotAvailable)
+            Err(MixerError::DeviceNotAvailable)
         }
     }
 
-    pub fn for_device(device: &Device) -> Result<Self, DefaultStreamConfigError> {
-        let config = device.default_output_config()?;
+    pub fn for_device(device: &Device) -> Result<Self, MixerError> {
+        Self::for_device_with_options(device, MixerOptions::default())
+    }
 
-        let channels = config.channels() as usize;
-        let sample_rate = config.sample_rate().0 as usize;
+    pub fn for_device_with_options(device: &Device, options: MixerOptions) -> Result<Self, MixerError> {
+        let config = OutputConfig::from(&device.default_output_config()?);
 
-        let inner = Arc::new(Mutex::new(Inner {
-            channels,
-            sample_rate,
-            sample_count: 0,
-            pending: vec![],
-            playing: vec![],
-            recorder: None,
-        }));
+        Self::for_config(device, config, options, AudioMixerBuilder::new())
+    }
+
+    // The request is the builder that the config was negotiated from. It is kept
+    // so that the same config can be asked for if the mixer moves device.
+    pub(crate) fn for_config(device: &Device, config: OutputConfig, options: MixerOptions, request: AudioMixerBuilder) -> Result<Self, MixerError> {
+        let mut mixer = Self::offline_with_options(config.channels, config.sample_rate, options);
+        mixer.request = request;
+
+        mixer.stream = Some(Self::move_to(device, config, &mixer.inner, &mixer.output, &mixer.errors)?);
+        mixer.start_recovery();
+
+        Ok(mixer)
+    }
+
+    // Creates a mixer that isn't attached to an audio device. Nothing is played
+    // and samples are only produced when you call one of the render methods,
+    // e.g. for rendering faster than real-time or on machines without a device.
+    pub fn offline(channels: usize, sample_rate: usize) -> Self {
+        Self::offline_with_options(channels, sample_rate, MixerOptions::default())
+    }
+
+    pub fn offline_with_options(channels: usize, sample_rate: usize, options: MixerOptions) -> Self {
+        let errors = Arc::new(ErrorHandler::default());
+        let (commands, inner) = Inner::new(channels, sample_rate, &options, errors.clone());
+        let (status, master_gain, master_levels) = (inner.status.clone(), inner.master.gain(), inner.meter.levels());
 
-        let _stream = match config.sample_format() {
-            SampleFormat::F32 => Self::build_stream::<f32>(device, config, inner.clone()),
-            SampleFormat::I16 => Self::build_stream::<i16>(device, config, inner.clone()),
-            SampleFormat::U16 => Self::build_stream::<u16>(device, config, inner.clone()),
-        };
+        let inner = Arc::new(Mutex::new(inner));
+        let output = Arc::new(Mutex::new(Output { channels, sample_rate, config: None, master_levels, generation: 0 }));
+
+        Self {
+            id: NEXT_MIXER_ID.fetch_add(1, Ordering::Relaxed),
+            commands,
+            status,
+            next_id: AtomicUsize::new(0),
+            buses: Mutex::default(),
+            master_gain,
+            inner,
+            output,
+            stream: None,
+            errors,
+            recovery_policy: options.recovery,
+            recovery: None,
+            notifier: Arc::new(Notifier::new()),
+            request: AudioMixerBuilder::new(),
+        }
+    }
 
-        Ok(Self { channels, sample_rate, inner, _stream })
+    pub fn is_offline(&self) -> bool {
+        self.output_config().is_none()
     }
 
-    pub fn set_device(&self, device: &Device) -> Result<Self, DefaultStreamConfigError> {
-        let mut inner = self.inner.lock().unwrap();
+    // The config that the device's stream was opened with or None if offline.
+    pub fn output_config(&self) -> Option<OutputConfig> {
+        self.output.lock().unwrap().config.clone()
+    }
+
+    // Errors that happen while the mixer is playing, such as the device being
+    // unplugged, are passed to this function. It is called from another thread
+    // so it shouldn't take long. Without a handler, errors are printed to stderr.
+    pub fn set_error_handler<F: FnMut(MixerError) + Send + 'static>(&self, function: F) {
+        self.errors.set_function(Some(Box::new(function)));
+    }
+
+    // Returns a channel that receives the errors that happen while the mixer is
+    // playing. This replaces the error handler if one was set.
+    pub fn errors(&self) -> Receiver<MixerError> {
+        let (sender, receiver) = channel();
+        self.set_error_handler(move |error| { let _ = sender.send(error); });
+
+        receiver
+    }
 
-        if inner.recorder.is_some() {
-            panic!("Please stop recording before calling AudioMixer::set_device");
+    // Moves the mix onto another device. Sources that are playing or waiting to
+    // start, buses and the recorder all carry over. If the device has a different
+    // number of channels or sample rate, the sources are converted to match and
+    // scheduled start frames and the position are scaled to the new rate.
+    //
+    // If the mixer was built with AudioMixerBuilder, the same config is asked
+    // for on the new device where it's supported. Effects on buses keep the
+    // configuration they were built with. This also works on an offline mixer,
+    // which then starts playing on the device.
+    pub fn set_device(&mut self, device: &Device) -> Result<(), MixerError> {
+        let config = self.request.config_for_device(device)?;
+
+        // The old stream keeps running until the new one has started so that
+        // the mixer still has a stream if this fails. It goes silent once the
+        // mix has moved. It might be on the recovery thread if the previous
+        // device was lost.
+        self.stream = Some(Self::move_to(device, config, &self.inner, &self.output, &self.errors)?);
+
+        if let Some(recovery) = &self.recovery {
+            let (reply, released) = channel();
+
+            if recovery.send(RecoveryCommand::Release(reply)).is_ok() { let _ = released.recv(); }
         }
 
-        let config = device.default_output_config()?;
-        let channels = config.channels() as usize;
+        self.start_recovery();
+
+        Ok(())
+    }
+
+    // Converts the mix to the device's format and starts playing it there. The
+    // caller drops the previous stream afterwards.
+    fn move_to(device: &Device, config: OutputConfig, inner: &Arc<Mutex<Inner>>, output: &Mutex<Output>, errors: &Arc<ErrorHandler>) -> Result<Stream, MixerError> {
+        Self::switch_to(config, inner, output, |config, generation| Self::play(device, config, inner.clone(), errors.clone(), generation))
+    }
+
+    // The stream is started before anything is changed so that the mix and the
+    // output are left as they were if it fails. No stream can take the lock
+    // until the mix has been converted and streams from an older generation
+    // play silence from then on, until they are dropped.
+    fn switch_to<F>(config: OutputConfig, inner: &Mutex<Inner>, output: &Mutex<Output>, play: F) -> Result<Stream, MixerError>
+        where F: FnOnce(&OutputConfig, usize) -> Result<Stream, MixerError>
+    {
+        let mut inner = lock_inner(inner);
+        let mut output = output.lock().unwrap();
+
+        let generation = output.generation + 1;
+        let stream = play(&config, generation)?;
+
+        inner.set_format(config.channels, config.sample_rate);
+        inner.generation = generation;
+
+        (output.channels, output.sample_rate, output.generation) = (config.channels, config.sample_rate, generation);
+        output.config = Some(config);
+
+        Ok(stream)
+    }
 
-        while inner.sample_count % channels != 0 { inner.next(); }
+    pub(crate) fn move_to_default_device(inner: &Arc<Mutex<Inner>>, output: &Mutex<Output>, errors: &Arc<ErrorHandler>, request: &AudioMixerBuilder) -> Result<Stream, MixerError> {
+        let device = default_host().default_output_device().ok_or(MixerError::DeviceNotAvailable)?;
+        let config = request.config_for_device(&device)?;
 
-        Self::for_device(device)
+        Self::move_to(&device, config, inner, output, errors)
+    }
+
+    fn start_recovery(&mut self) {
+        if self.recovery.is_some() || self.recovery_policy == RecoveryPolicy::Disabled { return; }
+
+        let (sender, receiver) = channel();
+        spawn_recovery_thread(self.inner.clone(), self.output.clone(), self.errors.clone(), self.request.clone(), receiver);
+
+        self.errors.set_recovery(sender.clone());
+        self.recovery = Some(sender);
     }
 
     pub fn output_devices() -> Vec<cpal::Device> {
@@ -82,73 +308,1127 @@ impl AudioMixer {
         output_devices
     }
 
-    pub fn add<S: Iterator<Item=f32> + Send + 'static>(&self, source: S) -> &Self {
-        self.inner.lock().unwrap().pending.push(Box::new(source)); self
+    pub fn add<S: Iterator<Item=f32> + Send + 'static>(&self, source: S) -> SourceHandle {
+        self.add_block_source_with(SourceOptions::default(), IntoBlocks::new(source))
+    }
+
+    pub fn add_to_bus<S: Iterator<Item=f32> + Send + 'static>(&self, bus: &Bus, source: S) -> SourceHandle {
+        self.add_with(SourceOptions { bus: Some(bus.clone()), ..Default::default() }, source)
+    }
+
+    // Starts the source at exactly this output frame, e.g. mixer.position() +
+    // mixer.frames_for_duration(delay). If the frame has already been played,
+    // the source starts on the next frame boundary like it would with add.
+    pub fn add_at<S: Iterator<Item=f32> + Send + 'static>(&self, frame: usize, source: S) -> SourceHandle {
+        self.add_with(SourceOptions { start_frame: Some(frame), ..Default::default() }, source)
+    }
+
+    pub fn add_with<S: Iterator<Item=f32> + Send + 'static>(&self, options: SourceOptions, source: S) -> SourceHandle {
+        self.add_block_source_with(options, IntoBlocks::new(source))
+    }
+
+    // Sources that implement BlockSource are asked for a block of samples at a
+    // time which avoids the overhead of calling into each source per sample.
+    pub fn add_block_source<S: BlockSource + Send + 'static>(&self, source: S) -> SourceHandle {
+        self.add_block_source_with(SourceOptions::default(), source)
+    }
+
+    pub fn add_block_source_with<S: BlockSource + Send + 'static>(&self, options: SourceOptions, source: S) -> SourceHandle {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let state = Arc::new(SourceState::default());
+
+        // Count the source as active straight away so that is_playing and wait
+        // don't return early before the audio thread has received the command.
+        self.status.active_sources.fetch_add(1, Ordering::Relaxed);
+
+        let bus = options.bus.and_then(|b| self.route_to(&b));
+        let start_frame = options.start_frame;
+        let (block_source, priority) = (Box::new(source), options.priority);
+
+        // Sources can't be stolen for being quiet until they have been measured.
+        let source = Source { id, block_source, state: state.clone(), bus, start_frame, offset: 0, samples_played: 0, priority, level: f32::MAX, fade: None };
+        self.send(Command::Add(source));
+
+        SourceHandle::new(id, state, self.commands.clone(), self.notifier.clone())
+    }
+
+    // Creates a bus that plays straight to the output. Sources can be routed into
+    // it with add_to_bus or via SourceOptions.
+    pub fn add_bus(&self, name: &str) -> Bus {
+        self.add_bus_with(name, None, |input| input)
+    }
+
+    // Creates a bus that feeds into another bus (or the output if None) and runs
+    // its samples through an effect chain first, e.g. |input| LowPassFilter::new(..., input).
+    pub fn add_bus_with<F, E>(&self, name: &str, output: Option<&Bus>, effects: F) -> Bus
+        where F: FnOnce(BusInput) -> E, E: BlockSource + Send + 'static
+    {
+        let max_block = MAX_BLOCK_SAMPLES;
+        let buffer = Arc::new(BusBuffer::new(max_block));
+        let effects = Box::new(effects(BusInput::new(buffer.clone())));
+        let output = output.and_then(|b| self.route_to(b));
+
+        // Hold the lock while sending so that buses are created in index order.
+        let mut buses = self.buses.lock().unwrap();
+        let bus = Bus::new(self.id, buses.len(), name);
+
+        let node = BusNode::new(&bus, output, buffer, effects, max_block);
+        self.send(Command::AddBus(node));
+
+        buses.push(bus.clone());
+        bus
+    }
+
+    pub fn bus(&self, name: &str) -> Option<Bus> {
+        self.buses.lock().unwrap().iter().find(|b| b.name() == name).cloned()
+    }
+
+    // Buses are indexed on the audio thread so one from another mixer could be
+    // out of bounds or refer to the wrong bus. Its command would already have
+    // been sent if this mixer created it, so its index is always valid there.
+    fn bus_index(&self, bus: &Bus) -> Result<usize, MixerError> {
+        if bus.mixer() != self.id { return Err(MixerError::ForeignBus { name: bus.name().to_string() }); }
+        Ok(bus.index())
+    }
+
+    // Sources and buses that are sent to another mixer's bus play straight to
+    // the output instead and the error is reported to the error handler.
+    fn route_to(&self, bus: &Bus) -> Option<usize> {
+        self.bus_index(bus).map_err(|error| self.errors.report(error)).ok()
     }
 
+    // Returns a channel that receives an event when any source starts, finishes
+    // or is removed. Events that happen before this is called aren't received.
+    // The channel holds up to 1024 events and drops the rest until it's read.
+    pub fn events(&self) -> Receiver<MixerEvent> {
+        let (sender, receiver) = event_channel();
+        self.send(Command::Subscribe(sender));
+
+        receiver
+    }
+
+    // How many events have been dropped because a channel was full.
+    pub fn dropped_events(&self) -> usize {
+        self.status.dropped_events.load(Ordering::Relaxed)
+    }
+
+    // Blocks until all sources have finished. The audio thread wakes this up
+    // when the last source finishes so it doesn't poll. Offline mixers only
+    // finish sources when they're rendered so this waits for another thread.
     pub fn wait(&self) {
-        loop {
-            if let Ok(inner) = self.inner.try_lock() {
-                if inner.pending.is_empty() && inner.playing.is_empty() {
-                    break;
-                }
-            }
+        self.notifier.wait(&self.commands, &self.idle(), None);
+    }
 
-            sleep(Duration::from_millis(10));
-        }
+    // Returns false if sources were still playing when the timeout expired.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        self.notifier.wait(&self.commands, &self.idle(), Some(timeout))
+    }
+
+    // Returns a future that completes when all sources have finished. Wrap it
+    // in your runtime's timeout function to stop waiting after a while.
+    pub fn wait_async(&self) -> WaitFuture {
+        self.notifier.wait_future(&self.commands, self.idle())
     }
 
     pub fn is_playing(&self) -> bool {
-        let inner = self.inner.lock().unwrap();
-        !inner.pending.is_empty() || !inner.playing.is_empty()
+        self.status.is_playing()
+    }
+
+    fn idle(&self) -> Condition {
+        Condition::MixerIdle(self.status.clone())
+    }
+
+    // The number of frames the mixer has produced so far. This is the clock that
+    // add_at uses. It is updated each time the audio device asks for samples.
+    pub fn position(&self) -> usize {
+        self.status.frame_count.load(Ordering::Relaxed)
     }
 
+    // The device's estimate of how long it takes for samples to reach the speaker
+    // after the audio thread has produced them. None if offline or not playing yet.
+    pub fn output_latency(&self) -> Option<Duration> {
+        self.status.clock.output_latency()
+    }
+
+    // The estimated time until a sample from a source that is added now will be
+    // heard. This includes waiting for the next callback and the output latency.
+    pub fn latency(&self) -> Option<Duration> {
+        let instant = self.instant_for_frame(self.position())?;
+        Some(instant.saturating_duration_since(Instant::now()))
+    }
+
+    // When the output frame will be (or was) played by the speaker. This can be
+    // used with add_at to sync audio with animation or video frames.
+    pub fn instant_for_frame(&self, frame: usize) -> Option<Instant> {
+        self.status.clock.instant_for_frame(frame)
+    }
+
+    pub fn frame_for_instant(&self, instant: Instant) -> Option<usize> {
+        self.status.clock.frame_for_instant(instant)
+    }
+
+    pub fn frames_for_duration(&self, duration: Duration) -> usize {
+        (duration.as_secs_f64() * self.sample_rate() as f64).round() as usize
+    }
+
+    // The gain applied to the whole mix. It can be changed while audio is playing.
+    pub fn master_gain(&self) -> DynamicFloat {
+        self.master_gain.clone()
+    }
+
+    // The levels of the mix after the master stage, i.e. what the device plays.
+    // The number of channels changes if the mixer moves to a device with more
+    // or fewer channels but the handle keeps working.
+    pub fn master_levels(&self) -> MeterLevels {
+        self.output.lock().unwrap().master_levels.clone()
+    }
+
+    // The channels and sample rate can change if the mixer moves to another
+    // device, including when it recovers from its device being lost.
     pub fn channels(&self) -> usize {
-        self.channels
+        self.output.lock().unwrap().channels
     }
 
     pub fn sample_rate(&self) -> usize {
-        self.sample_rate
+        self.output.lock().unwrap().sample_rate
+    }
+
+    // Plays the mix (or a bus) on another device as well, e.g. on a loopback
+    // device for streaming. It is converted to the device's default config and
+    // keeps playing until the Mirror is dropped.
+    pub fn add_mirror(&self, device: Device, options: MirrorOptions) -> Result<Mirror, MixerError> {
+        let tap = Arc::new(self.mirror_tap(options)?);
+        let mirror = Mirror::for_device(device, tap.clone(), self.errors.clone())?;
+
+        self.send(Command::AddMirror(tap));
+        Ok(mirror)
+    }
+
+    pub(crate) fn mirror_tap(&self, options: MirrorOptions) -> Result<MirrorTap, MixerError> {
+        let bus = options.bus.map(|b| self.bus_index(&b)).transpose()?;
+
+        let output = self.output.lock().unwrap();
+        Ok(MirrorTap::new(bus, output.channels, output.sample_rate, options.buffer_duration))
     }
 
     pub fn start_recording(&self, process_function: Box<dyn FnMut(crate::AudioFrame)>) {
-        self.inner.lock().unwrap().recorder = Some(AudioRecorder::new(process_function));
+        self.status.is_recording.store(true, Ordering::Relaxed);
+        self.send(Command::StartRecording(AudioRecorder::new(process_function)));
     }
 
     pub fn stop_recording(&self) {
-        self.inner.lock().unwrap().recorder = None;
+        self.status.is_recording.store(false, Ordering::Relaxed);
+        self.send(Command::StopRecording);
+    }
+
+    // Fills the buffer with the next samples of the mix. This is what the audio
+    // device would have played so it includes recording. The buffer should
+    // contain a whole number of frames, i.e. a multiple of the channels.
+    pub fn render(&self, buffer: &mut [f32]) {
+        if !self.is_offline() {
+            panic!("Please use AudioMixer::offline to render audio without a device");
+        }
+
+        let mut inner = lock_inner(&self.inner);
+        inner.render(buffer);
+
+        let (channels, sample_rate) = (inner.channels, inner.sample_rate);
+        if let Some(r) = inner.recorder.as_mut() { r.record_offline(buffer, channels, sample_rate); }
+    }
+
+    pub fn render_frames(&self, num_frames: usize) -> Vec<f32> {
+        let mut buffer = vec![0.; num_frames * self.channels()];
+        self.render(&mut buffer);
+        buffer
+    }
+
+    pub fn render_duration(&self, duration: Duration) -> Vec<f32> {
+        self.render_frames(self.frames_for_duration(duration))
     }
 
-    fn build_stream<S: Sample>(device: &Device, config: SupportedStreamConfig, inner: Arc<Mutex<Inner>>) -> Stream {
-        let config = &config.into();
+    #[cfg(feature = "wav")]
+    pub fn render_to_wav_file<P: AsRef<std::path::Path>>(&self, path: P, duration: Duration) -> Result<(), hound::Error> {
+        let mut writer = hound::WavWriter::create(path, hound::WavSpec {
+            channels: self.channels() as u16,
+            sample_rate: self.sample_rate() as u32,
+            sample_format: hound::SampleFormat::Float,
+            bits_per_sample: 32,
+        })?;
+
+        for sample in self.render_duration(duration) {
+            writer.write_sample(sample)?;
+        }
+
+        writer.finalize()
+    }
+
+    // The receiver lives as long as the stream so this only fails after the
+    // audio thread has gone away, in which case there's nothing to tell it.
+    fn send(&self, command: Command) {
+        let _ = self.commands.send(command);
+    }
+
+    fn play(device: &Device, config: &OutputConfig, inner: Arc<Mutex<Inner>>, errors: Arc<ErrorHandler>, generation: usize) -> Result<Stream, MixerError> {
+        let stream_config = config.stream_config();
+
+        let stream = match config.sample_format {
+            SampleFormat::F32 => Self::build_stream::<f32>(device, &stream_config, inner, errors, generation),
+            SampleFormat::I16 => Self::build_stream::<i16>(device, &stream_config, inner, errors, generation),
+            SampleFormat::U16 => Self::build_stream::<u16>(device, &stream_config, inner, errors, generation),
+        }?;
+
+        stream.play()?;
+        Ok(stream)
+    }
+
+    fn build_stream<S: Sample>(device: &Device, config: &StreamConfig, inner: Arc<Mutex<Inner>>, errors: Arc<ErrorHandler>, generation: usize) -> Result<Stream, MixerError> {
+        let mut mix = vec![];
 
         let stream = device.build_output_stream::<S, _, _>(config, move |out, info| {
-            let mut inner = inner.lock().unwrap();
-            out.iter_mut().for_each(|o| *o = Sample::from(&inner.next().unwrap()));
+            let mut inner = match inner.try_lock() {
+                Ok(inner) => inner,
+                Err(TryLockError::Poisoned(error)) => { inner.clear_poison(); error.into_inner() },
+                Err(TryLockError::WouldBlock) => return out.iter_mut().for_each(|o| *o = Sample::from(&0.)),
+            };
+
+            // The mix has moved to a newer stream that this one will be replaced by.
+            if inner.generation != generation { return out.iter_mut().for_each(|o| *o = Sample::from(&0.)); }
+
+            let first_frame = inner.sample_count / inner.channels;
+            let timestamp = info.timestamp();
+            let output_latency = timestamp.playback.duration_since(&timestamp.callback).unwrap_or_default();
+
+            inner.status.clock.update(first_frame, inner.sample_rate, output_latency, Instant::now());
+
+            mix.resize(out.len(), 0.);
+
+            // Sources and bus effects catch their own panics. This catches the
+            // rest so that a bug in the mix silences one buffer and carries on.
+            let rendered = catch_unwind(AssertUnwindSafe(|| {
+                inner.render(&mut mix);
+
+                match inner.dither.as_mut() {
+                    Some(dither) => dither.convert(&mix, out),
+                    None => out.iter_mut().zip(&mix).for_each(|(o, m)| *o = Sample::from(m)),
+                }
+            }));
+
+            if let Err(payload) = rendered {
+                out.iter_mut().for_each(|o| *o = Sample::from(&0.));
+                inner.errors.report_from_audio_thread(MixerError::MixPanicked { message: MixerError::panic_message(payload) });
+            }
 
             let (channels, sample_rate) = (inner.channels, inner.sample_rate);
-            inner.recorder.as_mut().map(|r| r.record(out, info, channels, sample_rate));
-        }, |error| {
-            eprintln!("output stream error: {}", error);
-        }).unwrap();
 
-        stream.play().unwrap();
-        stream
+            if let Some(recorder) = inner.recorder.as_mut() {
+                if let Err(payload) = catch_unwind(AssertUnwindSafe(|| recorder.record(out, info, channels, sample_rate))) {
+                    inner.recorder = None;
+                    inner.errors.report_from_audio_thread(MixerError::RecorderPanicked { message: MixerError::panic_message(payload) });
+                }
+            }
+        }, move |error| {
+            errors.stream_error(error, generation);
+        })?;
+
+        Ok(stream)
+    }
+}
+
+// The mix is left in a usable state between buffers so a panic that poisoned
+// the mutex, e.g. from an effect on the audio thread, doesn't stop it for good.
+fn lock_inner(inner: &Mutex<Inner>) -> MutexGuard<'_, Inner> {
+    inner.lock().unwrap_or_else(|error| { inner.clear_poison(); error.into_inner() })
+}
+
+impl Drop for AudioMixer {
+    fn drop(&mut self) {
+        if let Some(recovery) = &self.recovery { let _ = recovery.send(RecoveryCommand::Stop); }
+    }
+}
+
+impl Inner {
+    fn new(channels: usize, sample_rate: usize, options: &MixerOptions, errors: Arc<ErrorHandler>) -> (Sender<Command>, Self) {
+        let (sender, commands) = channel();
+        let status = Arc::new(Status::default());
+        let events = EventSenders::new(status.dropped_events.clone());
+
+        let master = MasterStage::new(&options.master, DynamicFloat::new(options.master.gain), channels, sample_rate);
+        let meter = MeterState::for_output(channels, sample_rate);
+        let block = vec![0.; MAX_BLOCK_SAMPLES];
+        let block_len = MAX_BLOCK_SAMPLES / channels * channels;
+        let dither = options.dither.as_ref().map(|d| Dither::new(d, channels));
+
+        (sender, Self { channels, sample_rate, sample_count: 0, commands, status, pending: vec![], playing: vec![], recorder: None, buses: vec![], master, meter, events, mirrors: vec![], errors, voice_limit: options.voice_limit.clone(), dither, block, block_len, generation: 0 })
+    }
+
+    // Converts everything in the mix to a new number of channels and sample rate.
+    // Commands are applied first so that sources added for the old format are
+    // converted too.
+    fn set_format(&mut self, channels: usize, sample_rate: usize) {
+        self.process_commands();
+        self.status.clock.reset();
+
+        // The new stream's timestamps aren't comparable with the old stream's.
+        if let Some(r) = self.recorder.as_mut() { r.restart_clock(); }
+
+        let (from_channels, from_rate) = (self.channels, self.sample_rate);
+        if (channels, sample_rate) == (from_channels, from_rate) { return; }
+
+        let scale_frame = |frame: usize| (frame as f64 * sample_rate as f64 / from_rate as f64).round() as usize;
+
+        for source in self.pending.iter_mut().chain(self.playing.iter_mut()) {
+            source.convert(from_channels, channels, from_rate, sample_rate);
+            source.start_frame = source.start_frame.map(scale_frame);
+        }
+
+        let frame = scale_frame(self.sample_count / from_channels);
+
+        self.channels = channels;
+        self.sample_rate = sample_rate;
+        self.sample_count = frame * channels;
+        self.block_len = MAX_BLOCK_SAMPLES / channels * channels;
+        self.master.set_format(channels, sample_rate);
+        self.meter.set_format(channels, sample_rate);
+        if let Some(d) = self.dither.as_mut() { d.set_channels(channels); }
+        self.mirrors.iter().for_each(|tap| tap.set_format(channels, sample_rate));
+
+        self.status.frame_count.store(frame, Ordering::Relaxed);
+    }
+
+    fn process_commands(&mut self) {
+        while let Ok(command) = self.commands.try_recv() {
+            match command {
+                Command::Add(source) => self.pending.push(source),
+                Command::AddBus(bus) => self.buses.push(bus),
+                Command::Remove(id) => self.remove(id),
+                Command::StartRecording(recorder) => self.recorder = Some(recorder),
+                Command::StopRecording => self.recorder = None,
+                Command::AddMirror(tap) => self.mirrors.push(tap),
+                Command::Subscribe(sender) => self.events.subscribe(sender),
+            }
+        }
+    }
+
+    // Sources can only start on a frame boundary so that they play on the right
+    // channels. This splits the buffer if it doesn't start on one, which can
+    // happen when rendering offline with buffers that aren't whole frames.
+    fn render(&mut self, out: &mut [f32]) {
+        self.process_commands();
+
+        let misaligned = (self.channels - self.sample_count % self.channels) % self.channels;
+        let (head, tail) = out.split_at_mut(misaligned.min(out.len()));
+
+        self.mix_block(head, false);
+
+        for chunk in tail.chunks_mut(self.block_len) {
+            self.start_pending(chunk.len());
+            self.mix_block(chunk, true);
+        }
+
+        self.status.frame_count.store(self.sample_count / self.channels, Ordering::Relaxed);
+    }
+
+    // Starts the pending sources that are due to start within the next block.
+    // Scheduled sources are offset so that they start partway into the block.
+    fn start_pending(&mut self, len: usize) {
+        let first_frame = self.sample_count / self.channels;
+        let end_frame = first_frame + len / self.channels;
+
+        let (pending, channels) = (&mut self.pending, self.channels);
+
+        for mut source in pending.extract_if(.., |s| s.start_frame.is_none_or(|f| f < end_frame)) {
+            source.offset = source.start_frame.map_or(0, |f| f.saturating_sub(first_frame) * channels);
+            let frame = first_frame + source.offset / channels;
+
+            if Self::steal_voice(&mut self.playing, self.voice_limit.as_ref(), &source, channels, self.sample_rate) {
+                self.events.send(MixerEvent::Started { id: source.id, frame });
+                self.playing.push(source);
+            } else {
+                self.status.finish(&source);
+                self.events.send(MixerEvent::Stolen { id: source.id, frame });
+            }
+        }
+    }
+
+    // Makes room for the starting source by fading out a playing source if the
+    // voice limit has been reached. Returns false if the starting source should
+    // be stolen instead. Sources that are fading out count as voices until they
+    // are silent and the playing sources are in the order they started so the
+    // first is oldest.
+    fn steal_voice(playing: &mut [Source], limit: Option<&VoiceLimit>, starting: &Source, channels: usize, sample_rate: usize) -> bool {
+        let Some(limit) = limit else { return true };
+        if limit.max_voices == 0 { return false; }
+
+        let fading = |s: &Source| s.fade.as_ref().is_some_and(|f| f.remaining > 0);
+        let voices = |playing: &[Source]| playing.iter().filter(|s| s.fade.is_none() || fading(s)).count();
+
+        if voices(playing) < limit.max_voices { return true; }
+
+        let mut candidates = playing.iter().enumerate().filter(|(_, s)| s.fade.is_none());
+
+        let victim = match limit.policy {
+            StealingPolicy::Oldest => candidates.next(),
+            StealingPolicy::Quietest => candidates.min_by(|(_, a), (_, b)| a.level.total_cmp(&b.level)),
+            StealingPolicy::LowestPriority => candidates.min_by_key(|(_, s)| s.priority),
+        }.map(|(index, s)| (index, s.priority));
+
+        // This is checked first so that a source that doesn't start can't cut
+        // short a voice that is fading out.
+        if limit.policy == StealingPolicy::LowestPriority && victim.is_some_and(|(_, priority)| starting.priority < priority) {
+            return false;
+        }
+
+        // A voice that was stolen earlier is cut short to make room before
+        // stealing another, so there's never more than one voice over the limit.
+        while voices(playing) >= limit.max_voices {
+            let Some(source) = playing.iter_mut().filter(|s| fading(s)).min_by_key(|s| s.fade.as_ref().map(|f| f.remaining)) else { break };
+            source.fade.as_mut().unwrap().remaining = 0;
+        }
+
+        if voices(playing) < limit.max_voices { return true; }
+        let Some((index, _)) = victim else { return false };
+
+        let length = (limit.fade_out.as_secs_f64() * sample_rate as f64) as usize * channels;
+        playing[index].fade = Some(Fade { remaining: length, length });
+
+        true
+    }
+
+    fn mix_block(&mut self, out: &mut [f32], in_sync: bool) {
+        let len = out.len();
+        if len == 0 { return; }
+
+        out.fill(0.);
+        self.buses.iter_mut().for_each(|b| b.mix[..len].fill(0.));
+
+        let (block, buses, status, events, errors) = (&mut self.block[..len], &mut self.buses, &self.status, &mut self.events, &self.errors);
+        let (first_sample, channels) = (self.sample_count, self.channels);
+
+        // Levels are only needed to choose which voice to steal.
+        let measure = self.voice_limit.as_ref().is_some_and(|l| l.policy == StealingPolicy::Quietest);
+
+        self.playing.retain_mut(|s| {
+            let offset = std::mem::take(&mut s.offset);
+            let remaining = len - offset;
+
+            let stopped = in_sync && s.state.is_stopped();
+
+            // A panicking source is removed so that it can't poison the mutex
+            // and stop the rest of the mix from playing.
+            let result = if stopped { Ok(0) } else { catch_unwind(AssertUnwindSafe(|| s.block_source.fill_block(&mut block[..remaining]))) };
+
+            let (mut written, panicked) = match result {
+                Ok(written) => (written, false),
+                Err(payload) => {
+                    errors.report_from_audio_thread(MixerError::SourcePanicked { id: s.id, message: MixerError::panic_message(payload) });
+                    (0, true)
+                },
+            };
+
+            if let Some(fade) = &mut s.fade {
+                written = written.min(fade.remaining);
+
+                for sample in &mut block[..written] {
+                    *sample *= fade.remaining as f32 / fade.length as f32;
+                    fade.remaining -= 1;
+                }
+            }
+
+            if measure {
+                s.level = block[..written].iter().map(|b| b * b).sum::<f32>() / written.max(1) as f32;
+            }
+
+            let target = match s.bus { Some(i) => &mut buses[i].mix[offset..len], None => &mut out[offset..] };
+            target.iter_mut().zip(&block[..written]).for_each(|(o, b)| *o += b);
+            s.samples_played += written;
+            s.state.set_frames_played(s.samples_played / channels);
+
+            let stolen = s.fade.as_ref().is_some_and(|f| f.remaining == 0);
+            let finished = written < remaining || stolen;
+
+            if finished {
+                let frame = (first_sample + offset + written).div_ceil(channels);
+
+                let event = match (stopped || panicked, stolen) {
+                    (true, _) => MixerEvent::Removed { id: s.id, frame },
+                    (_, true) => MixerEvent::Stolen { id: s.id, frame },
+                    _ => MixerEvent::Finished { id: s.id, frame },
+                };
+
+                status.finish(s);
+                events.send(event);
+            }
+
+            !finished
+        });
+
+        // Buses can only output to buses that were created before them so this
+        // processes every bus after all of the buses that feed into it.
+        for i in (0..self.buses.len()).rev() {
+            let (earlier, later) = self.buses.split_at_mut(i);
+            let bus = &mut later[0];
+
+            // A bus whose effects panic carries on playing without them.
+            if let Err(payload) = catch_unwind(AssertUnwindSafe(|| bus.process(len))) {
+                bus.bypass_effects();
+                bus.mix[..len].fill(0.);
+                self.errors.report_from_audio_thread(MixerError::BusPanicked { index: i, message: MixerError::panic_message(payload) });
+            }
+
+            let target = match bus.output { Some(j) => &mut earlier[j].mix[..len], None => &mut *out };
+            target.iter_mut().zip(&bus.mix[..len]).for_each(|(o, b)| *o += b);
+        }
+
+        self.master.process(out);
+        self.meter.process_block(out);
+
+        // Mirrors are removed once their stream has been dropped.
+        self.mirrors.retain(|tap| Arc::strong_count(tap) > 1);
+
+        for tap in &self.mirrors {
+            let samples = match tap.bus { Some(i) => &self.buses[i].mix[..len], None => &*out };
+            tap.latest().buffer.push_frames(samples.iter().copied(), channels);
+        }
+
+        self.sample_count += len;
+    }
+
+    fn remove(&mut self, id: usize) {
+        let pending = self.pending.extract_if(.., |s| s.id == id);
+        let playing = self.playing.extract_if(.., |s| s.id == id);
+
+        let frame = self.sample_count / self.channels;
+
+        for source in pending.chain(playing) {
+            self.status.finish(&source);
+            self.events.send(MixerEvent::Removed { id, frame });
+        }
+    }
+}
+
+impl Source {
+    fn convert(&mut self, from_channels: usize, to_channels: usize, from_rate: usize, to_rate: usize) {
+        let block_source = std::mem::replace(&mut self.block_source, Box::new(IntoBlocks::new(std::iter::empty())));
+
+        let samples = FromBlocks::new(MAX_BLOCK_SAMPLES / from_channels * from_channels, block_source);
+
+        // Prime the resampler with the source's next frame rather than silence
+        // so that sources which are already playing don't click.
+        let options = ResampleOptions { compensate_edges: true, ..Default::default() };
+        let resampled = IntoSampleRate::with_options(from_rate, to_rate, from_channels, options, samples);
+        let converted = IntoChannels::new(from_channels, to_channels, resampled);
+
+        self.block_source = Box::new(IntoBlocks::new(converted));
+        self.samples_played = self.samples_played / from_channels * to_channels;
+
+        let scale = |samples: usize| (samples / from_channels) * to_rate / from_rate * to_channels;
+        self.fade = self.fade.take().map(|f| Fade { remaining: scale(f.remaining), length: scale(f.length).max(1) });
+    }
+}
+
+// Sources are finished before their events are sent so that anything woken by
+// an event sees the source as finished.
+impl Status {
+    pub fn is_playing(&self) -> bool {
+        self.active_sources.load(Ordering::Relaxed) > 0
+    }
+
+    fn finish(&self, source: &Source) {
+        source.state.finish();
+        self.active_sources.fetch_sub(1, Ordering::Relaxed);
     }
 }
 
-impl Iterator for Inner {
-    type Item = f32;
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cpal::StreamError;
+
+    #[test]
+    fn it_renders_the_sum_of_the_playing_sources_when_offline() {
+        let mixer = AudioMixer::offline(2, 44100);
+
+        mixer.add([1., 2., 3., 4.].into_iter());
+        mixer.add([10., 20.].into_iter());
+
+        assert_eq!(mixer.render_frames(3), vec![11., 22., 3., 4., 0., 0.]);
+        assert!(!mixer.is_playing());
+    }
+
+    #[test]
+    fn it_starts_sources_that_are_added_between_renders_on_a_frame_boundary() {
+        let mixer = AudioMixer::offline(2, 44100);
+
+        let mut buffer = vec![0.; 3];
+        mixer.render(&mut buffer);
+
+        mixer.add([1., 2.].into_iter());
+        mixer.render(&mut buffer);
+
+        assert_eq!(buffer, vec![0., 1., 2.]);
+    }
+
+    #[test]
+    fn it_renders_a_duration_based_on_the_sample_rate_and_channels() {
+        let mixer = AudioMixer::offline(2, 100);
+        let output = mixer.render_duration(Duration::from_millis(500));
+
+        assert_eq!(output.len(), 100);
+    }
+
+    #[test]
+    fn it_records_audio_while_rendering_offline() {
+        let mixer = AudioMixer::offline(1, 2);
+        let recorded = Arc::new(Mutex::new(vec![]));
+        let recorded_clone = recorded.clone();
+
+        mixer.start_recording(Box::new(move |frame| {
+            recorded_clone.lock().unwrap().push((frame.audio_data.to_vec(), frame.elapsed_time));
+        }));
+
+        mixer.add([1., 2., 3.].into_iter());
+        mixer.render_frames(2);
+        mixer.render_frames(2);
+
+        let recorded = recorded.lock().unwrap();
+        assert_eq!(recorded[0], (vec![1., 2.], Duration::from_secs(0)));
+        assert_eq!(recorded[1], (vec![3., 0.], Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn it_returns_a_handle_that_can_query_the_source() {
+        let mixer = AudioMixer::offline(2, 44100);
+
+        let handle1 = mixer.add([1., 2., 3., 4.].into_iter());
+        let handle2 = mixer.add([1., 2., 3., 4., 5., 6.].into_iter());
+        assert_ne!(handle1.id(), handle2.id());
+
+        mixer.render_frames(1);
+        assert_eq!(handle1.frames_played(), 1);
+        assert!(handle1.is_playing());
+
+        mixer.render_frames(2);
+        assert_eq!(handle1.frames_played(), 2);
+        assert_eq!(handle2.frames_played(), 3);
+        assert!(!handle1.is_playing());
+    }
+
+    #[test]
+    fn it_can_stop_a_source_via_its_handle() {
+        let mixer = AudioMixer::offline(1, 44100);
+
+        let handle = mixer.add([1., 2., 3.].into_iter());
+        mixer.add([10., 10., 10.].into_iter());
+
+        assert_eq!(mixer.render_frames(1), vec![11.]);
+        handle.stop();
+
+        assert_eq!(mixer.render_frames(2), vec![10., 10.]);
+        assert!(!handle.is_playing());
+    }
+
+    #[test]
+    fn it_can_remove_a_source_before_the_next_block_via_its_handle() {
+        let mixer = AudioMixer::offline(1, 44100);
+
+        let handle = mixer.add([1., 2., 3.].into_iter());
+        handle.remove();
+        assert!(!handle.is_playing());
+
+        assert_eq!(mixer.render_frames(1), vec![0.]);
+        assert!(!mixer.is_playing());
+    }
+
+    #[test]
+    fn it_applies_the_bus_volume_to_sources_routed_into_it() {
+        let mixer = AudioMixer::offline(1, 44100);
+        let music = mixer.add_bus("music");
+
+        mixer.add_to_bus(&music, [1., 2., 3.].into_iter());
+        mixer.add([10., 10., 10.].into_iter());
+
+        music.set_volume(0.5);
+        assert_eq!(mixer.render_frames(2), vec![10.5, 11.]);
+
+        music.set_muted(true);
+        assert_eq!(mixer.render_frames(1), vec![10.]);
+    }
+
+    #[test]
+    fn it_runs_the_bus_through_its_effect_chain() {
+        let mixer = AudioMixer::offline(1, 44100);
+        let bus = mixer.add_bus_with("sfx", None, |input| AdjustVolume::new(2., input));
+
+        mixer.add_to_bus(&bus, [1., 2.].into_iter());
+        assert_eq!(mixer.render_frames(3), vec![2., 4., 0.]);
+    }
+
+    #[test]
+    fn it_allows_buses_to_feed_into_other_buses() {
+        let mixer = AudioMixer::offline(1, 44100);
+
+        let master = mixer.add_bus("master");
+        let voice = mixer.add_bus_with("voice", Some(&master), |input| input);
+
+        master.set_volume(0.5);
+        voice.set_volume(0.5);
+
+        mixer.add_to_bus(&voice, [4., 8.].into_iter());
+        mixer.add_to_bus(&master, [2., 2.].into_iter());
+
+        assert_eq!(mixer.render_frames(2), vec![2., 3.]);
+        assert_eq!(mixer.bus("voice").unwrap().index(), voice.index());
+    }
+
+    #[test]
+    fn it_plays_sources_sent_to_another_mixers_bus_on_the_output_and_reports_it() {
+        let (mixer, other) = (AudioMixer::offline(1, 44100), AudioMixer::offline(1, 44100));
+        let errors = mixer.errors();
+
+        mixer.add_bus_with("music", None, |input| AdjustVolume::new(0.5, input));
+        let foreign = other.add_bus("music");
+
+        mixer.add_to_bus(&foreign, [1.].into_iter());
+        assert_eq!(mixer.render_frames(1), vec![1.]);
+
+        assert!(matches!(errors.try_recv(), Ok(MixerError::ForeignBus { name }) if name == "music"));
+        assert!(matches!(mixer.mirror_tap(MirrorOptions { bus: Some(foreign), ..Default::default() }), Err(MixerError::ForeignBus { .. })));
+    }
+
+    #[test]
+    fn it_runs_the_mix_through_the_master_stage() {
+        let master = MasterOptions { gain: 0.5, soft_clip: true, ..Default::default() };
+        let mixer = AudioMixer::offline_with_options(1, 44100, MixerOptions { master, ..Default::default() });
+
+        mixer.add([0.5, 1., 2.].into_iter());
+        mixer.add([0.5, 1., 2.].into_iter());
+
+        let output = mixer.render_frames(3);
+        assert_eq!(output[0], 0.5);
+        assert!(output[1] > 0.5 && output[2] < 1.);
+
+        mixer.master_gain().set(0.);
+        assert_eq!(mixer.render_frames(1), vec![0.]);
+    }
+
+    #[test]
+    fn it_starts_scheduled_sources_at_exactly_the_requested_frame() {
+        let mixer = AudioMixer::offline(2, 44100);
+
+        mixer.add_at(3, [1., 2., 3., 4.].into_iter());
+        mixer.add_at(1, [5., 6.].into_iter());
+
+        assert_eq!(mixer.render_frames(2), vec![0., 0., 5., 6.]);
+        assert_eq!(mixer.render_frames(3), vec![0., 0., 1., 2., 3., 4.]);
+    }
+
+    #[test]
+    fn it_reports_its_position_in_frames() {
+        let mixer = AudioMixer::offline(2, 100);
+        assert_eq!(mixer.position(), 0);
+
+        mixer.render_duration(Duration::from_millis(250));
+        assert_eq!(mixer.position(), 25);
+
+        mixer.add_at(mixer.position() + mixer.frames_for_duration(Duration::from_millis(20)), [1., 1.].into_iter());
+        let output = mixer.render_frames(3);
+
+        assert_eq!(output, vec![0., 0., 0., 0., 1., 1.]);
+    }
+
+    #[test]
+    fn it_sends_events_when_sources_start_and_finish() {
+        let mixer = AudioMixer::offline(2, 44100);
+        let events = mixer.events();
+
+        let handle1 = mixer.add([1., 2., 3., 4.].into_iter());
+        let handle2 = mixer.add_at(1, [1., 2., 3.].into_iter());
+        mixer.render_frames(4);
+
+        let events = events.try_iter().collect::<Vec<_>>();
+
+        assert_eq!(events, vec![
+            MixerEvent::Started { id: handle1.id(), frame: 0 },
+            MixerEvent::Started { id: handle2.id(), frame: 1 },
+            MixerEvent::Finished { id: handle1.id(), frame: 2 },
+            MixerEvent::Finished { id: handle2.id(), frame: 3 },
+        ]);
+    }
+
+    #[test]
+    fn it_drops_and_counts_events_once_a_channel_is_full() {
+        let mixer = AudioMixer::offline(1, 44100);
+        let events = mixer.events();
+
+        (0..600).for_each(|_| { mixer.add([1.].into_iter()); });
+        mixer.render_frames(2);
+
+        assert_eq!(events.try_iter().count(), 1024);
+        assert_eq!(mixer.dropped_events(), 176);
+    }
+
+    #[test]
+    fn it_sends_an_event_when_a_source_is_stopped_or_removed() {
+        let mixer = AudioMixer::offline(1, 44100);
+        let events = mixer.events();
+
+        let handle1 = mixer.add([1., 2., 3.].into_iter());
+        let handle2 = mixer.add([1., 2., 3.].into_iter());
+        mixer.render_frames(1);
+
+        handle1.stop();
+        handle2.remove();
+        mixer.render_frames(1);
+
+        let events = events.try_iter().skip(2).collect::<Vec<_>>();
+
+        assert_eq!(events, vec![
+            MixerEvent::Removed { id: handle2.id(), frame: 1 },
+            MixerEvent::Removed { id: handle1.id(), frame: 1 },
+        ]);
+    }
+
+    #[test]
+    fn it_leaves_the_mix_and_output_as_they_were_if_the_new_stream_fails() {
+        let mixer = AudioMixer::offline(1, 100);
+        mixer.add([1., 2.].into_iter());
+
+        let config = OutputConfig { channels: 2, sample_rate: 200, sample_format: SampleFormat::F32, buffer_size: None };
+        let result = AudioMixer::switch_to(config, &mixer.inner, &mixer.output, |_, _| Err(MixerError::DeviceNotAvailable));
+
+        assert!(matches!(result, Err(MixerError::DeviceNotAvailable)));
+        assert!(mixer.is_offline());
+        assert_eq!((mixer.channels(), mixer.sample_rate()), (1, 100));
+        assert_eq!(mixer.render_frames(2), vec![1., 2.]);
+    }
+
+    #[test]
+    fn it_converts_the_sources_when_the_format_changes() {
+        let mixer = AudioMixer::offline(1, 2);
+        let events = mixer.events();
+
+        let handle = mixer.add([1., 2., 3., 4.].into_iter());
+        let scheduled = mixer.add_at(2, [10., 10.].into_iter());
+        assert_eq!(mixer.render_frames(1), vec![1.]);
+
+        mixer.inner.lock().unwrap().set_format(2, 4);
+        assert_eq!(mixer.position(), 2);
+
+        // The mono source is upsampled and played on both channels. It carries
+        // on from where it was rather than ramping up from silence.
+        let mut buffer = [0.; 6];
+        mixer.render(&mut buffer);
+
+        assert_eq!(buffer[..4], [2., 2., 2.5, 2.5]);
+        assert_eq!(handle.frames_played(), 4);
+
+        // The scheduled source starts at the equivalent frame at the new rate.
+        let started = events.try_iter().find(|e| e.id() == scheduled.id());
+        assert_eq!(started, Some(MixerEvent::Started { id: scheduled.id(), frame: 4 }));
+    }
+
+    #[test]
+    fn it_steals_the_oldest_voice_when_the_limit_is_reached() {
+        let voice_limit = VoiceLimit { max_voices: 2, policy: StealingPolicy::Oldest, fade_out: Duration::ZERO };
+        let mixer = AudioMixer::offline_with_options(1, 1000, MixerOptions { voice_limit: Some(voice_limit), ..Default::default() });
+        let events = mixer.events();
+
+        let handle1 = mixer.add([1.; 4].into_iter());
+        let handle2 = mixer.add([2.; 4].into_iter());
+        assert_eq!(mixer.render_frames(1), vec![3.]);
+
+        let handle3 = mixer.add([4.; 4].into_iter());
+        assert_eq!(mixer.render_frames(1), vec![6.]);
+
+        assert!(!handle1.is_playing());
+        assert!(handle2.is_playing() && handle3.is_playing());
+        assert!(events.try_iter().any(|e| e == MixerEvent::Stolen { id: handle1.id(), frame: 1 }));
+    }
+
+    #[test]
+    fn it_fades_out_the_stolen_voice() {
+        let voice_limit = VoiceLimit { max_voices: 1, policy: StealingPolicy::Oldest, fade_out: Duration::from_millis(4) };
+        let mixer = AudioMixer::offline_with_options(1, 1000, MixerOptions { voice_limit: Some(voice_limit), ..Default::default() });
+
+        let handle1 = mixer.add([1.; 10].into_iter());
+        mixer.render_frames(1);
+
+        mixer.add([0.; 10].into_iter());
+        assert_eq!(mixer.render_frames(5), vec![1., 0.75, 0.5, 0.25, 0.]);
+        assert!(!handle1.is_playing());
+    }
+
+    #[test]
+    fn it_counts_voices_that_are_fading_out_towards_the_limit() {
+        let voice_limit = VoiceLimit { max_voices: 1, policy: StealingPolicy::Oldest, fade_out: Duration::from_millis(4) };
+        let mixer = AudioMixer::offline_with_options(1, 1000, MixerOptions { voice_limit: Some(voice_limit), ..Default::default() });
+
+        let handle1 = mixer.add([1.; 10].into_iter());
+        let handle2 = mixer.add([2.; 10].into_iter());
+        let handle3 = mixer.add([4.; 10].into_iter());
+        mixer.render_frames(1);
+
+        assert!(!handle1.is_playing() && handle2.is_playing() && handle3.is_playing());
+        assert_eq!(mixer.render_frames(4), vec![5.5, 5., 4.5, 4.]);
+        assert!(!handle2.is_playing());
+    }
+
+    #[test]
+    fn it_does_not_cut_short_a_fading_voice_for_a_source_that_does_not_start() {
+        let voice_limit = VoiceLimit { max_voices: 1, policy: StealingPolicy::LowestPriority, fade_out: Duration::from_millis(4) };
+        let mixer = AudioMixer::offline_with_options(1, 1000, MixerOptions { voice_limit: Some(voice_limit), ..Default::default() });
+
+        mixer.add_with(SourceOptions { priority: 1, ..Default::default() }, [1.; 10].into_iter());
+        mixer.render_frames(1);
+
+        mixer.add_with(SourceOptions { priority: 2, ..Default::default() }, [0.; 10].into_iter());
+        let rejected = mixer.add_with(SourceOptions { priority: 0, ..Default::default() }, [4.; 10].into_iter());
+
+        assert_eq!(mixer.render_frames(4), vec![1., 0.75, 0.5, 0.25]);
+        assert!(!rejected.is_playing());
+    }
+
+    #[test]
+    fn it_does_not_play_anything_when_the_voice_limit_is_zero() {
+        let voice_limit = VoiceLimit { max_voices: 0, ..Default::default() };
+        let mixer = AudioMixer::offline_with_options(1, 1000, MixerOptions { voice_limit: Some(voice_limit), ..Default::default() });
+        let events = mixer.events();
+
+        let handle = mixer.add([1.; 4].into_iter());
+        assert_eq!(mixer.render_frames(1), vec![0.]);
+
+        assert!(!handle.is_playing());
+        assert_eq!(events.try_recv(), Ok(MixerEvent::Stolen { id: handle.id(), frame: 0 }));
+    }
+
+    #[test]
+    fn it_does_not_start_a_source_with_a_lower_priority_than_the_playing_voices() {
+        let voice_limit = VoiceLimit { max_voices: 1, policy: StealingPolicy::LowestPriority, fade_out: Duration::ZERO };
+        let mixer = AudioMixer::offline_with_options(1, 1000, MixerOptions { voice_limit: Some(voice_limit), ..Default::default() });
+
+        let handle1 = mixer.add_with(SourceOptions { priority: 1, ..Default::default() }, [1.; 4].into_iter());
+        let handle2 = mixer.add_with(SourceOptions { priority: 0, ..Default::default() }, [2.; 4].into_iter());
+        let handle3 = mixer.add_with(SourceOptions { priority: 2, ..Default::default() }, [4.; 4].into_iter());
+
+        assert_eq!(mixer.render_frames(1), vec![4.]);
+        assert!(!handle1.is_playing() && !handle2.is_playing() && handle3.is_playing());
+    }
+
+    #[test]
+    fn it_steals_the_quietest_voice_once_the_voices_have_been_measured() {
+        let voice_limit = VoiceLimit { max_voices: 2, policy: StealingPolicy::Quietest, fade_out: Duration::ZERO };
+        let mixer = AudioMixer::offline_with_options(1, 1000, MixerOptions { voice_limit: Some(voice_limit), ..Default::default() });
+
+        let handle1 = mixer.add([1.; 4].into_iter());
+        let handle2 = mixer.add([0.5; 4].into_iter());
+        mixer.render_frames(1);
+
+        mixer.add([0.25; 4].into_iter());
+        assert_eq!(mixer.render_frames(1), vec![1.25]);
+        assert!(handle1.is_playing() && !handle2.is_playing());
+    }
+
+    #[test]
+    fn it_writes_the_mix_or_a_bus_to_its_mirrors() {
+        let mixer = AudioMixer::offline(1, 10);
+        let bus = mixer.add_bus("music");
+
+        let mix = Arc::new(mixer.mirror_tap(MirrorOptions::default()).unwrap());
+        let music = Arc::new(mixer.mirror_tap(MirrorOptions { bus: Some(bus.clone()), ..Default::default() }).unwrap());
+        mixer.send(Command::AddMirror(mix.clone()));
+        mixer.send(Command::AddMirror(music.clone()));
+
+        mixer.add([1., 1.].into_iter());
+        mixer.add_to_bus(&bus, [2., 2.].into_iter());
+        mixer.render_frames(2);
+
+        assert_eq!(std::iter::from_fn(|| mix.buffer.pop()).collect::<Vec<_>>(), vec![3., 3.]);
+        assert_eq!(std::iter::from_fn(|| music.buffer.pop()).collect::<Vec<_>>(), vec![2., 2.]);
+
+        // The tap is removed once nothing is reading from it.
+        drop(music);
+        mixer.render_frames(1);
+        assert_eq!(mixer.inner.lock().unwrap().mirrors.len(), 1);
+    }
+
+    #[test]
+    fn it_meters_the_output_of_the_mix() {
+        let mixer = AudioMixer::offline(1, 100);
+        let levels = mixer.master_levels();
+
+        mixer.master_gain().set(0.5);
+        mixer.add([1., -1., 1., -1., 1.].into_iter());
+        mixer.render_frames(5);
+
+        assert_eq!((levels.peak(0), levels.rms(0)), (0.5, 0.5));
+        assert!(!levels.has_clipped());
+    }
+
+    #[test]
+    fn it_keeps_the_master_levels_handle_working_when_the_format_changes() {
+        let mixer = AudioMixer::offline(1, 100);
+        let levels = mixer.master_levels();
+
+        mixer.inner.lock().unwrap().set_format(2, 100);
+        mixer.add([0.25, 0.5].into_iter().cycle().take(10));
+        mixer.render(&mut [0.; 10]);
+
+        assert_eq!(levels.channels(), 2);
+        assert_eq!((levels.peak(0), levels.peak(1)), (0.25, 0.5));
+
+        // A channel that no longer exists reads as silent rather than panicking.
+        mixer.inner.lock().unwrap().set_format(1, 100);
+        assert_eq!((levels.channels(), levels.peak(1)), (1, 0.));
+    }
+
+    #[test]
+    fn it_removes_and_reports_a_source_that_panics_without_stopping_the_mix() {
+        let mixer = AudioMixer::offline(1, 44100);
+        let (errors, events) = (mixer.errors(), mixer.events());
+
+        let bad = mixer.add([1., 1.].into_iter().chain(std::iter::from_fn(|| panic!("bad generator"))));
+        let good = mixer.add([2., 2., 2., 2.].into_iter());
+
+        // The samples from the block that the source was filling are discarded.
+        assert_eq!(mixer.render_frames(4), vec![2., 2., 2., 2.]);
+        assert!(!bad.is_playing() && good.is_playing());
+
+        let error = errors.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(error, MixerError::SourcePanicked { id, message } if id == bad.id() && message == "bad generator"));
+        assert!(events.try_iter().any(|e| e == MixerEvent::Removed { id: bad.id(), frame: 0 }));
+
+        mixer.add([4.].into_iter());
+        assert_eq!(mixer.render_frames(1), vec![4.]);
+    }
+
+    #[test]
+    fn it_bypasses_the_effects_of_a_bus_that_panics() {
+        let mixer = AudioMixer::offline(1, 44100);
+        let errors = mixer.errors();
+
+        let bus = mixer.add_bus_with("sfx", None, |input| IntoBlocks::new(input.map(|s| if s > 1. { panic!("bad effect") } else { s })));
+        mixer.add_to_bus(&bus, [1., 2.].into_iter());
+
+        // The block that the effects were processing is silenced.
+        assert_eq!(mixer.render_frames(2), vec![0., 0.]);
+
+        let error = errors.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(error, MixerError::BusPanicked { index: 0, message } if message == "bad effect"));
+
+        mixer.add_to_bus(&bus, [1., 2.].into_iter());
+        assert_eq!(mixer.render_frames(2), vec![1., 2.]);
+    }
 
-    fn next(&mut self) -> Option<f32> {
-        let in_sync = self.sample_count % self.channels == 0;
-        if in_sync { self.playing.append(&mut self.pending); }
+    #[test]
+    fn it_passes_errors_to_the_most_recent_error_channel() {
+        let mixer = AudioMixer::offline(1, 44100);
 
-        let mut total = 0.;
+        let first = mixer.errors();
+        let second = mixer.errors();
 
-        self.playing.retain_mut(|s| s.next().map(|f| total += f).is_some());
-        self.sample_count += 1;
+        mixer.errors.stream_error(StreamError::DeviceNotAvailable, 0);
 
-        Some(total)
+        assert!(first.try_recv().is_err());
+        assert!(matches!(second.try_recv(), Ok(MixerError::Stream(StreamError::DeviceNotAvailable))));
     }
 }
@@ -0,0 +1,253 @@
+use crate::*;
+use cpal::{BufferSize, StreamConfig, SupportedBufferSize, SupportedStreamConfigRange};
+
+// Builds an AudioMixer with a specific channel count, sample rate, sample format
+// or buffer size rather than the device's default config. Anything that isn't
+// requested is taken from the default config where the device allows it.
+//
+// The buffer size is in frames. Small buffers reduce latency and large buffers
+// mean the audio thread wakes up less often which saves power.
+
+#[derive(Clone, Debug, Default)]
+pub struct AudioMixerBuilder {
+    channels: Option<usize>,
+    sample_rate: Option<usize>,
+    sample_format: Option<SampleFormat>,
+    buffer_size: Option<usize>,
+    options: MixerOptions,
+}
+
+// The config that the stream was actually opened with. A buffer size of None
+// means the host's default was used.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutputConfig {
+    pub channels: usize,
+    pub sample_rate: usize,
+    pub sample_format: SampleFormat,
+    pub buffer_size: Option<usize>,
+}
+
+impl AudioMixerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn channels(mut self, channels: usize) -> Self {
+        self.channels = Some(channels);
+        self
+    }
+
+    pub fn sample_rate(mut self, sample_rate: usize) -> Self {
+        self.sample_rate = Some(sample_rate);
+        self
+    }
+
+    pub fn sample_format(mut self, sample_format: SampleFormat) -> Self {
+        self.sample_format = Some(sample_format);
+        self
+    }
+
+    pub fn buffer_size(mut self, frames: usize) -> Self {
+        self.buffer_size = Some(frames);
+        self
+    }
+
+    pub fn options(mut self, options: MixerOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn build(self) -> Result<AudioMixer, MixerError> {
+        match default_host().default_output_device() {
+            Some(device) => self.build_for_device(&device),
+            None => Err(MixerError::DeviceNotAvailable),
+        }
+    }
+
+    pub fn build_for_device(self, device: &Device) -> Result<AudioMixer, MixerError> {
+        let supported = device.supported_output_configs()?.collect::<Vec<_>>();
+        let default = OutputConfig::from(&device.default_output_config()?);
+
+        let config = self.negotiate(&supported, &default)?;
+        AudioMixer::for_config(device, config, self.options.clone(), self)
+    }
+
+    // The config to use when the mixer moves to another device. The requested
+    // parameters are kept where the device supports them and the others fall
+    // back to the device's defaults, rather than failing to move at all.
+    pub(crate) fn config_for_device(&self, device: &Device) -> Result<OutputConfig, MixerError> {
+        let supported = device.supported_output_configs()?.collect::<Vec<_>>();
+        let default = OutputConfig::from(&device.default_output_config()?);
+
+        Ok(self.negotiate_or_default(&supported, &default))
+    }
+
+    fn negotiate_or_default(&self, supported: &[SupportedStreamConfigRange], default: &OutputConfig) -> OutputConfig {
+        let mut request = self.clone();
+
+        loop {
+            match request.negotiate(supported, default) {
+                Ok(config) => return config,
+                Err(MixerError::UnsupportedChannels { .. }) => request.channels = None,
+                Err(MixerError::UnsupportedSampleFormat { .. }) => request.sample_format = None,
+                Err(MixerError::UnsupportedSampleRate { .. }) => request.sample_rate = None,
+                Err(_) => request.buffer_size = None,
+            }
+        }
+    }
+
+    // Narrows down the supported configs one requested parameter at a time so
+    // that the error names the first parameter the device can't provide.
+    fn negotiate(&self, supported: &[SupportedStreamConfigRange], default: &OutputConfig) -> Result<OutputConfig, MixerError> {
+        let mut candidates = supported.to_vec();
+
+        if let Some(requested) = self.channels {
+            let supported = distinct(candidates.iter().map(|c| c.channels() as usize));
+            candidates.retain(|c| c.channels() as usize == requested);
+
+            if candidates.is_empty() { return Err(MixerError::UnsupportedChannels { requested, supported }); }
+        }
+
+        if let Some(requested) = self.sample_format {
+            let supported = distinct(candidates.iter().map(|c| c.sample_format()));
+            candidates.retain(|c| c.sample_format() == requested);
+
+            if candidates.is_empty() { return Err(MixerError::UnsupportedSampleFormat { requested, supported }); }
+        }
+
+        if let Some(requested) = self.sample_rate {
+            let supported = distinct(candidates.iter().map(sample_rate_range));
+            candidates.retain(|c| { let (min, max) = sample_rate_range(c); (min..=max).contains(&requested) });
+
+            if candidates.is_empty() { return Err(MixerError::UnsupportedSampleRate { requested, supported }); }
+        }
+
+        if let Some(requested) = self.buffer_size {
+            let supported = distinct(candidates.iter().filter_map(buffer_size_range));
+
+            // Some hosts can't say which buffer sizes they support so allow those.
+            candidates.retain(|c| buffer_size_range(c).is_none_or(|(min, max)| (min..=max).contains(&requested)));
+
+            if candidates.is_empty() { return Err(MixerError::UnsupportedBufferSize { requested, supported }); }
+        }
+
+        // Prefer the default channels and sample format if they weren't requested.
+        let best = candidates.iter().min_by_key(|c| (c.channels() as usize != default.channels, c.sample_format() != default.sample_format));
+
+        // The device didn't report any configs so there's nothing to check against.
+        let Some(best) = best else { return Ok(default.clone()) };
+
+        let (min, max) = sample_rate_range(best);
+        let sample_rate = self.sample_rate.unwrap_or(default.sample_rate.clamp(min, max));
+
+        Ok(OutputConfig { channels: best.channels() as usize, sample_rate, sample_format: best.sample_format(), buffer_size: self.buffer_size })
+    }
+}
+
+impl OutputConfig {
+    pub(crate) fn stream_config(&self) -> StreamConfig {
+        let buffer_size = match self.buffer_size { Some(n) => BufferSize::Fixed(n as u32), None => BufferSize::Default };
+
+        StreamConfig { channels: self.channels as u16, sample_rate: cpal::SampleRate(self.sample_rate as u32), buffer_size }
+    }
+}
+
+impl From<&SupportedStreamConfig> for OutputConfig {
+    fn from(config: &SupportedStreamConfig) -> Self {
+        Self {
+            channels: config.channels() as usize,
+            sample_rate: config.sample_rate().0 as usize,
+            sample_format: config.sample_format(),
+            buffer_size: None,
+        }
+    }
+}
+
+fn sample_rate_range(config: &SupportedStreamConfigRange) -> (usize, usize) {
+    (config.min_sample_rate().0 as usize, config.max_sample_rate().0 as usize)
+}
+
+fn buffer_size_range(config: &SupportedStreamConfigRange) -> Option<(usize, usize)> {
+    match config.buffer_size() {
+        SupportedBufferSize::Range { min, max } => Some((*min as usize, *max as usize)),
+        SupportedBufferSize::Unknown => None,
+    }
+}
+
+fn distinct<T: PartialEq>(values: impl Iterator<Item=T>) -> Vec<T> {
+    let mut distinct = vec![];
+
+    for value in values {
+        if !distinct.contains(&value) { distinct.push(value); }
+    }
+
+    distinct
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn range(channels: u16, min: u32, max: u32, format: SampleFormat) -> SupportedStreamConfigRange {
+        let buffer_size = SupportedBufferSize::Range { min: 64, max: 4096 };
+        SupportedStreamConfigRange::new(channels, cpal::SampleRate(min), cpal::SampleRate(max), buffer_size, format)
+    }
+
+    fn supported() -> Vec<SupportedStreamConfigRange> {
+        vec![
+            range(1, 8000, 48000, SampleFormat::I16),
+            range(2, 8000, 48000, SampleFormat::I16),
+            range(2, 44100, 96000, SampleFormat::F32),
+        ]
+    }
+
+    fn default() -> OutputConfig {
+        OutputConfig { channels: 2, sample_rate: 44100, sample_format: SampleFormat::F32, buffer_size: None }
+    }
+
+    #[test]
+    fn it_uses_the_default_config_for_parameters_that_were_not_requested() {
+        let config = AudioMixerBuilder::new().buffer_size(256).negotiate(&supported(), &default()).unwrap();
+
+        assert_eq!(config, OutputConfig { buffer_size: Some(256), ..default() });
+    }
+
+    #[test]
+    fn it_picks_a_config_that_supports_the_requested_parameters() {
+        let builder = AudioMixerBuilder::new().sample_rate(22050);
+        let config = builder.negotiate(&supported(), &default()).unwrap();
+
+        assert_eq!(config, OutputConfig { channels: 2, sample_rate: 22050, sample_format: SampleFormat::I16, buffer_size: None });
+
+        let builder = AudioMixerBuilder::new().channels(1);
+        let config = builder.negotiate(&supported(), &default()).unwrap();
+
+        assert_eq!(config, OutputConfig { channels: 1, sample_rate: 44100, sample_format: SampleFormat::I16, buffer_size: None });
+    }
+
+    #[test]
+    fn it_returns_an_error_naming_the_parameter_that_is_not_supported() {
+        let builder = AudioMixerBuilder::new().channels(6);
+        let error = builder.negotiate(&supported(), &default()).unwrap_err();
+
+        assert!(matches!(error, MixerError::UnsupportedChannels { requested: 6, supported } if supported == [1, 2]));
+
+        let builder = AudioMixerBuilder::new().channels(1).sample_rate(96000);
+        let error = builder.negotiate(&supported(), &default()).unwrap_err();
+
+        assert!(matches!(error, MixerError::UnsupportedSampleRate { requested: 96000, supported } if supported == [(8000, 48000)]));
+
+        let builder = AudioMixerBuilder::new().buffer_size(16);
+        let error = builder.negotiate(&supported(), &default()).unwrap_err();
+
+        assert!(matches!(error, MixerError::UnsupportedBufferSize { requested: 16, supported } if supported == [(64, 4096)]));
+    }
+
+    #[test]
+    fn it_falls_back_to_the_default_for_parameters_that_another_device_does_not_support() {
+        let builder = AudioMixerBuilder::new().channels(6).sample_rate(22050).buffer_size(256);
+        let config = builder.negotiate_or_default(&supported(), &default());
+
+        assert_eq!(config, OutputConfig { channels: 2, sample_rate: 22050, sample_format: SampleFormat::I16, buffer_size: Some(256) });
+    }
+}
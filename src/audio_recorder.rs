@@ -2,25 +2,50 @@ use crate::*;
 
 pub struct AudioRecorder {
     start_time: Option<StreamInstant>,
+    elapsed_before: Duration,
+    elapsed_time: Duration,
     process_function: Box<dyn FnMut(AudioFrame)>,
     frame_number: usize,
+    sample_count: usize,
 }
 
 impl AudioRecorder {
     pub fn new(process_function: Box<dyn FnMut(AudioFrame)>) -> Self {
-        Self { start_time: None, process_function, frame_number: 0 }
+        Self { start_time: None, elapsed_before: Duration::ZERO, elapsed_time: Duration::ZERO, process_function, frame_number: 0, sample_count: 0 }
+    }
+
+    // The elapsed time is measured from the stream's timestamps. Their origin is
+    // different for each stream so this is called when the mixer moves devices
+    // and the elapsed time carries on from where it was on the old stream.
+    pub(crate) fn restart_clock(&mut self) {
+        self.start_time = None;
+        self.elapsed_before = self.elapsed_time;
     }
 
     pub fn record<S: Sample>(&mut self, samples: &[S], info: &OutputCallbackInfo, channels: usize, sample_rate: usize) {
+        let start_time = self.start_time.get_or_insert_with(|| info.timestamp().callback);
+        let current_time = info.timestamp().playback;
+        let elapsed_time = self.elapsed_before + current_time.duration_since(start_time).unwrap_or_default();
+
+        self.elapsed_time = elapsed_time;
+        self.process(into_f32_samples(samples), channels, sample_rate, elapsed_time);
+    }
+
+    // There is no audio device when rendering offline so derive the elapsed
+    // time from the number of samples that have been recorded so far instead.
+    pub fn record_offline(&mut self, samples: &[f32], channels: usize, sample_rate: usize) {
+        let seconds = self.sample_count as f64 / (channels * sample_rate) as f64;
+        let elapsed_time = Duration::from_secs_f64(seconds);
+
+        self.process(Cow::Borrowed(samples), channels, sample_rate, elapsed_time);
+    }
+
+    fn process(&mut self, audio_data: Cow<[f32]>, channels: usize, sample_rate: usize, elapsed_time: Duration) {
         self.frame_number += 1;
+        self.sample_count += audio_data.len();
 
-        let audio_data = into_f32_samples(samples);
         let frame_number = self.frame_number;
 
-        let start_time = self.start_time.get_or_insert_with(|| info.timestamp().callback);
-        let current_time = info.timestamp().playback;
-        let elapsed_time = current_time.duration_since(start_time).unwrap();
-
         (self.process_function)(AudioFrame {
             audio_data, channels, sample_rate, frame_number, elapsed_time,
         });
@@ -46,5 +71,13 @@ pub struct AudioFrame<'a> {
     pub elapsed_time: Duration,
 }
 
+impl AudioFrame<'_> {
+    // Converts the audio data to 16-bit samples, e.g. for a 16-bit wav file.
+    // Keep using the same Dither across frames so its noise shaping carries on.
+    pub fn to_i16_samples(&self, dither: &mut Dither) -> Vec<i16> {
+        self.audio_data.iter().map(|s| dither.next_i16(*s)).collect()
+    }
+}
+
 unsafe impl Send for AudioRecorder {}
 unsafe impl Sync for AudioRecorder {}
@@ -0,0 +1,139 @@
+// An alternative to Iterator<Item=f32> for sources that can produce many samples
+// at once. The mixer pulls a block from each source per callback rather than
+// calling through a Box<dyn Iterator> for every sample, which adds up quickly
+// when there are lots of sources playing.
+//
+// Implementations must fill the block with channel-interlaced samples and return
+// how many were written. Writing fewer than block.len() means the source has
+// finished and it won't be asked for any more samples.
+
+pub trait BlockSource {
+    fn fill_block(&mut self, block: &mut [f32]) -> usize;
+}
+
+impl<B: BlockSource + ?Sized> BlockSource for Box<B> {
+    fn fill_block(&mut self, block: &mut [f32]) -> usize {
+        (**self).fill_block(block)
+    }
+}
+
+// Bridges a plain iterator so it can be used where a BlockSource is expected.
+// The iterator is still called once per sample but the calls are statically
+// dispatched so they can be inlined into the loop. It is also an Iterator so
+// that block-based adapters (e.g. AdjustVolume) can be built on top of it.
+
+pub struct IntoBlocks<S: Iterator<Item=f32>> {
+    source: S,
+}
+
+impl<S: Iterator<Item=f32>> IntoBlocks<S> {
+    pub fn new(source: S) -> Self {
+        Self { source }
+    }
+}
+
+impl<S: Iterator<Item=f32>> BlockSource for IntoBlocks<S> {
+    fn fill_block(&mut self, block: &mut [f32]) -> usize {
+        fill_from_iterator(&mut self.source, block)
+    }
+}
+
+impl<S: Iterator<Item=f32>> Iterator for IntoBlocks<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.source.next()
+    }
+}
+
+// The reverse of IntoBlocks. It reads a block at a time from the source and
+// yields the samples one by one so that a BlockSource can be run through the
+// iterator-based adapters, e.g. when the mixer moves to a device with a
+// different number of channels and its sources need converting.
+
+pub struct FromBlocks<S: BlockSource> {
+    source: S,
+    buffer: Vec<f32>,
+    position: usize,
+    len: usize,
+    finished: bool,
+}
+
+impl<S: BlockSource> FromBlocks<S> {
+    pub fn new(block_size: usize, source: S) -> Self {
+        Self { source, buffer: vec![0.; block_size.max(1)], position: 0, len: 0, finished: false }
+    }
+}
+
+impl<S: BlockSource> Iterator for FromBlocks<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position == self.len {
+            if self.finished { return None; }
+
+            self.len = self.source.fill_block(&mut self.buffer);
+            self.finished = self.len < self.buffer.len();
+            self.position = 0;
+
+            if self.len == 0 { return None; }
+        }
+
+        self.position += 1;
+        Some(self.buffer[self.position - 1])
+    }
+}
+
+// Adapters that don't benefit from processing a whole block at once use this to
+// implement BlockSource in terms of their own (statically dispatched) next.
+pub fn fill_from_iterator<I: Iterator<Item=f32>>(iterator: &mut I, block: &mut [f32]) -> usize {
+    for (i, sample) in block.iter_mut().enumerate() {
+        match iterator.next() {
+            Some(s) => *sample = s,
+            None => return i,
+        }
+    }
+
+    block.len()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn it_fills_blocks_from_an_iterator_until_it_runs_out() {
+        let mut source = IntoBlocks::new([1., 2., 3., 4., 5.].into_iter());
+        let mut block = [0.; 3];
+
+        assert_eq!(source.fill_block(&mut block), 3);
+        assert_eq!(block, [1., 2., 3.]);
+
+        assert_eq!(source.fill_block(&mut block), 2);
+        assert_eq!(block[..2], [4., 5.]);
+
+        assert_eq!(source.fill_block(&mut block), 0);
+    }
+
+    #[test]
+    fn it_yields_the_samples_of_a_block_source_one_at_a_time() {
+        let source = IntoBlocks::new([1., 2., 3., 4., 5.].into_iter());
+        let iterator = FromBlocks::new(2, source);
+
+        assert_eq!(iterator.collect::<Vec<_>>(), vec![1., 2., 3., 4., 5.]);
+    }
+
+    #[test]
+    fn it_produces_the_same_samples_as_the_iterator_when_adapters_are_chained() {
+        let buffer = ReusableBuffer::new(0, vec![1., 2., 3., 4., 5., 6., 7.]);
+
+        let mut iterator = AdjustVolume::new(0.5, StoppableAudio::new(false, buffer.reuse_from(0)));
+        let mut blocks = AdjustVolume::new(0.5, StoppableAudio::new(false, buffer.reuse_from(0)));
+
+        let expected = iterator.by_ref().collect::<Vec<_>>();
+        let mut actual = [0.; 10];
+
+        let written = blocks.fill_block(&mut actual[0..4]) + blocks.fill_block(&mut actual[4..]);
+        assert_eq!(actual[..written], expected);
+    }
+}
@@ -0,0 +1,155 @@
+use crate::*;
+
+// Buses group sources together so that they can be controlled as one, e.g. to
+// provide separate music and sound effect volume sliders. Sources are routed
+// into a bus via SourceOptions and each bus feeds either the mixer's output or
+// another bus that was created before it.
+//
+// The Bus struct is a handle to the bus. Its volume and muted controls are
+// dynamic so they can be changed from any thread while audio is playing. It
+// remembers which mixer created it so that other mixers can reject it.
+
+#[derive(Clone, Debug)]
+pub struct Bus {
+    mixer: usize,
+    index: usize,
+    name: Arc<str>,
+    volume: DynamicFloat,
+    muted: DynamicBool,
+}
+
+impl Bus {
+    pub(crate) fn new(mixer: usize, index: usize, name: &str) -> Self {
+        Self { mixer, index, name: name.into(), volume: DynamicFloat::new(1.), muted: DynamicBool::new(false) }
+    }
+
+    pub(crate) fn mixer(&self) -> usize {
+        self.mixer
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn volume(&self) -> DynamicFloat {
+        self.volume.clone()
+    }
+
+    pub fn set_volume(&self, volume: f32) {
+        self.volume.set(volume);
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted.get()
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.set(muted);
+    }
+}
+
+// The source at the start of a bus's effect chain. It yields the samples that
+// were mixed into the bus for the current block. Effects must produce one output
+// sample per input sample, i.e. they can't change the channels or sample rate.
+//
+// It never returns None because the bus is refilled on each block. If an effect
+// asks for more samples than are available it receives silence instead.
+
+pub struct BusInput {
+    buffer: Arc<BusBuffer>,
+}
+
+pub(crate) struct BusBuffer {
+    samples: Box<[AtomicF32]>,
+    len: AtomicUsize,
+    position: AtomicUsize,
+}
+
+impl BusInput {
+    pub(crate) fn new(buffer: Arc<BusBuffer>) -> Self {
+        Self { buffer }
+    }
+}
+
+impl Iterator for BusInput {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let position = self.buffer.position.fetch_add(1, Ordering::Relaxed);
+        let len = self.buffer.len.load(Ordering::Relaxed);
+
+        if position < len {
+            Some(self.buffer.samples[position].load(Ordering::Relaxed))
+        } else {
+            Some(0.)
+        }
+    }
+}
+
+impl BlockSource for BusInput {
+    fn fill_block(&mut self, block: &mut [f32]) -> usize {
+        let position = self.buffer.position.fetch_add(block.len(), Ordering::Relaxed);
+        let len = self.buffer.len.load(Ordering::Relaxed);
+
+        for (i, sample) in block.iter_mut().enumerate() {
+            let index = position + i;
+            *sample = if index < len { self.buffer.samples[index].load(Ordering::Relaxed) } else { 0. };
+        }
+
+        block.len()
+    }
+}
+
+// The audio thread's side of a bus. It is owned by the mixer's Inner struct.
+pub(crate) struct BusNode {
+    pub output: Option<usize>,
+    pub mix: Vec<f32>,
+    volume: DynamicFloat,
+    muted: DynamicBool,
+    buffer: Arc<BusBuffer>,
+    effects: Box<dyn BlockSource + Send>,
+    block: Vec<f32>,
+}
+
+impl BusNode {
+    pub fn new(bus: &Bus, output: Option<usize>, buffer: Arc<BusBuffer>, effects: Box<dyn BlockSource + Send>, max_block: usize) -> Self {
+        let (volume, muted) = (bus.volume.clone(), bus.muted.clone());
+
+        Self { output, mix: vec![0.; max_block], volume, muted, buffer, effects, block: vec![0.; max_block] }
+    }
+
+    // Runs the samples mixed into the bus through its effects and applies its
+    // volume. The result is left in self.mix for the caller to route onwards.
+    pub fn process(&mut self, len: usize) {
+        for (atomic, sample) in self.buffer.samples.iter().zip(&self.mix[..len]) {
+            atomic.store(*sample, Ordering::Relaxed);
+        }
+
+        self.buffer.len.store(len, Ordering::Relaxed);
+        self.buffer.position.store(0, Ordering::Relaxed);
+
+        let written = self.effects.fill_block(&mut self.block[..len]);
+        let volume = if self.muted.get() { 0. } else { self.volume.get() };
+
+        for (i, sample) in self.mix[..len].iter_mut().enumerate() {
+            *sample = if i < written { self.block[i] * volume } else { 0. };
+        }
+    }
+
+    // Replaces the effects with the bus's input, e.g. after they have panicked.
+    pub fn bypass_effects(&mut self) {
+        self.effects = Box::new(BusInput::new(self.buffer.clone()));
+    }
+}
+
+impl BusBuffer {
+    pub fn new(max_block: usize) -> Self {
+        let samples = (0..max_block).map(|_| AtomicF32::new(0.)).collect();
+
+        Self { samples, len: AtomicUsize::new(0), position: AtomicUsize::new(0) }
+    }
+}
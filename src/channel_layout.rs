@@ -0,0 +1,148 @@
+use Speaker::*;
+
+// The speaker layouts that IntoChannels::with_layouts converts between. The
+// channels are in the order of the default WAVEFORMATEXTENSIBLE channel masks,
+// so 5.1 is L, R, C, LFE, Lb, Rb (its surrounds are the back speakers) and 7.1
+// is L, R, C, LFE, Lb, Rb, Ls, Rs (back then side).
+//
+// Speakers that the output layout doesn't have are folded into their nearest
+// neighbors at -3dB, following ITU-R BS.775, e.g. 5.1 to stereo is L + 0.707C +
+// 0.707Lb. The LFE channel is left out of downmixes. When upmixing a layout
+// without a center channel, e.g. stereo to 5.1, the center is derived from
+// the left and right channels.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelLayout {
+    Mono,
+    Stereo,
+    Quad,
+    Surround51,
+    Surround71,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Speaker {
+    Left,
+    Right,
+    Center,
+    Lfe,
+    BackLeft,
+    BackRight,
+    SideLeft,
+    SideRight,
+}
+
+const MINUS_3DB: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+impl ChannelLayout {
+    pub fn for_channels(channels: usize) -> Option<Self> {
+        match channels {
+            1 => Some(Self::Mono),
+            2 => Some(Self::Stereo),
+            4 => Some(Self::Quad),
+            6 => Some(Self::Surround51),
+            8 => Some(Self::Surround71),
+            _ => None,
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.speakers().len()
+    }
+
+    // Mono is treated as a single center speaker.
+    fn speakers(&self) -> &'static [Speaker] {
+        match self {
+            Self::Mono => &[Center],
+            Self::Stereo => &[Left, Right],
+            Self::Quad => &[Left, Right, BackLeft, BackRight],
+            Self::Surround51 => &[Left, Right, Center, Lfe, BackLeft, BackRight],
+            Self::Surround71 => &[Left, Right, Center, Lfe, BackLeft, BackRight, SideLeft, SideRight],
+        }
+    }
+
+    // Returns the gain from each input channel to each output channel, one row
+    // of to.channels() gains per input channel like ChannelMatrix. If normalize
+    // is set, the gains into an output that add up to more than one are scaled
+    // down so that it can't clip.
+    pub(crate) fn matrix(self, to: Self, normalize: bool) -> Vec<f32> {
+        let (inputs, outputs) = (self.speakers(), to.speakers());
+        let mut matrix = vec![0.; inputs.len() * outputs.len()];
+
+        for (i, speaker) in inputs.iter().enumerate() {
+            for (target, gain) in fold(*speaker, outputs) {
+                let o = outputs.iter().position(|s| *s == target).unwrap();
+                matrix[i * outputs.len() + o] += gain;
+            }
+        }
+
+        let derive_center = !inputs.contains(&Center) && outputs.contains(&Center) && outputs.contains(&Left);
+
+        if let Some(c) = outputs.iter().position(|s| *s == Center).filter(|_| derive_center) {
+            for (i, speaker) in inputs.iter().enumerate() {
+                if matches!(speaker, Left | Right) { matrix[i * outputs.len() + c] = 0.5; }
+            }
+        }
+
+        if normalize {
+            for o in 0..outputs.len() {
+                let sum = matrix.iter().skip(o).step_by(outputs.len()).map(|g| g.abs()).sum::<f32>();
+                if sum > 1. { matrix.iter_mut().skip(o).step_by(outputs.len()).for_each(|g| *g /= sum); }
+            }
+        }
+
+        matrix
+    }
+}
+
+// Returns the speakers in the output that a speaker's signal goes to and their
+// gains. Only mono doesn't have left and right speakers and it has a center.
+fn fold(speaker: Speaker, outputs: &[Speaker]) -> Vec<(Speaker, f32)> {
+    if outputs.contains(&speaker) { return vec![(speaker, 1.)]; }
+    let has = |s| outputs.contains(&s);
+
+    match speaker {
+        Lfe => vec![],
+        Left | Right => vec![(Center, MINUS_3DB)],
+        Center => vec![(Left, MINUS_3DB), (Right, MINUS_3DB)],
+        SideLeft if has(BackLeft) => vec![(BackLeft, 1.)],
+        SideRight if has(BackRight) => vec![(BackRight, 1.)],
+        BackLeft if has(SideLeft) => vec![(SideLeft, 1.)],
+        BackRight if has(SideRight) => vec![(SideRight, 1.)],
+        SideLeft | BackLeft => fold(Left, outputs).into_iter().map(|(s, g)| (s, g * MINUS_3DB)).collect(),
+        SideRight | BackRight => fold(Right, outputs).into_iter().map(|(s, g)| (s, g * MINUS_3DB)).collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_folds_surround_channels_into_their_neighbors_at_minus_three_db() {
+        let matrix = ChannelLayout::Surround51.matrix(ChannelLayout::Stereo, false);
+
+        assert_eq!(matrix, vec![
+            1., 0.,
+            0., 1.,
+            MINUS_3DB, MINUS_3DB,
+            0., 0.,
+            MINUS_3DB, 0.,
+            0., MINUS_3DB,
+        ]);
+
+        let matrix = ChannelLayout::Surround51.matrix(ChannelLayout::Mono, false);
+        let rounded = matrix.iter().map(|g| (g * 1000.).round() / 1000.).collect::<Vec<_>>();
+
+        assert_eq!(rounded, vec![0.707, 0.707, 1., 0., 0.5, 0.5]);
+    }
+
+    #[test]
+    fn it_maps_the_back_speakers_of_5_1_to_the_back_speakers_of_7_1() {
+        let matrix = ChannelLayout::Surround51.matrix(ChannelLayout::Surround71, false);
+        let gains = |input: usize| matrix[input * 8..(input + 1) * 8].to_vec();
+
+        assert_eq!(gains(4), vec![0., 0., 0., 0., 1., 0., 0., 0.]);
+        assert_eq!(gains(5), vec![0., 0., 0., 0., 0., 1., 0., 0.]);
+    }
+}
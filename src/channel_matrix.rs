@@ -0,0 +1,131 @@
+use crate::*;
+
+// Routes each input channel to each output channel with a gain, e.g. to swap
+// the left and right channels, put a mono voice on the center speaker only or
+// send a stereo pair to the rear speakers as well as the front.
+//
+// The gains have one row per input channel with one gain per output channel, so
+// gains[1][0] is how much of the second input channel goes to the first output.
+// Use DynamicFloat gains to change the routing while the source is playing.
+
+pub struct ChannelMatrix<G: MaybeDynamic<f32>, S: Iterator<Item=f32>> {
+    gains: Vec<Vec<G>>,
+    source: S,
+    current: Vec<Vec<f32>>,
+    frame: Vec<f32>,
+    output: Vec<f32>,
+    counter: usize,
+}
+
+impl<G: MaybeDynamic<f32>, S: Iterator<Item=f32>> ChannelMatrix<G, S> {
+    pub fn new(gains: Vec<Vec<G>>, source: S) -> Self {
+        let outputs = gains.first().map_or(0, |row| row.len());
+
+        assert!(outputs > 0, "The matrix must have at least one input and output channel");
+        assert!(gains.iter().all(|row| row.len() == outputs), "Each input channel must have a gain for every output channel");
+
+        let current = gains.iter().map(|row| row.iter().map(|g| g.get()).collect()).collect();
+        let (frame, output) = (vec![0.; gains.len()], vec![0.; outputs]);
+
+        Self { gains, source, current, frame, output, counter: 0 }
+    }
+
+    pub fn input_channels(&self) -> usize {
+        self.frame.len()
+    }
+
+    pub fn output_channels(&self) -> usize {
+        self.output.len()
+    }
+
+    // Static gains can't change so they are only read once, in new.
+    fn next_frame(&mut self) -> Option<()> {
+        self.frame[0] = self.source.next()?;
+
+        for sample in &mut self.frame[1..] {
+            *sample = self.source.next().unwrap_or(0.);
+        }
+
+        if G::is_dynamic() {
+            for (current, gains) in self.current.iter_mut().zip(&self.gains) {
+                current.iter_mut().zip(gains).for_each(|(c, g)| *c = g.get());
+            }
+        }
+
+        self.output.fill(0.);
+
+        for (sample, gains) in self.frame.iter().zip(&self.current) {
+            for (output, gain) in self.output.iter_mut().zip(gains) {
+                *output += sample * gain;
+            }
+        }
+
+        Some(())
+    }
+}
+
+impl<G: MaybeDynamic<f32>, S: Iterator<Item=f32>> Iterator for ChannelMatrix<G, S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.counter == 0 { self.next_frame()?; }
+
+        let sample = self.output[self.counter];
+        self.counter = (self.counter + 1) % self.output.len();
+
+        Some(sample)
+    }
+}
+
+impl<G: MaybeDynamic<f32>, S: Iterator<Item=f32>> BlockSource for ChannelMatrix<G, S> {
+    fn fill_block(&mut self, block: &mut [f32]) -> usize {
+        fill_from_iterator(self, block)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_swaps_the_left_and_right_channels() {
+        let input = [1., 2., 3., 4.].into_iter();
+        let output = ChannelMatrix::new(vec![vec![0., 1.], vec![1., 0.]], input).collect::<Vec<_>>();
+        assert_eq!(output, vec![2., 1., 4., 3.]);
+    }
+
+    #[test]
+    fn it_routes_and_mixes_channels_with_gains() {
+        // Mono to the center of 5.1 and stereo to the front and rear of quad.
+        let input = [1., 2.].into_iter();
+        let output = ChannelMatrix::new(vec![vec![0., 0., 1., 0., 0., 0.]], input).collect::<Vec<_>>();
+        assert_eq!(output, vec![0., 0., 1., 0., 0., 0., 0., 0., 2., 0., 0., 0.]);
+
+        let input = [1., 2.].into_iter();
+        let output = ChannelMatrix::new(vec![vec![1., 0., 0.5, 0.], vec![0., 1., 0., 0.5]], input).collect::<Vec<_>>();
+        assert_eq!(output, vec![1., 2., 0.5, 1.]);
+
+        let input = [1., 2., 3.].into_iter();
+        let output = ChannelMatrix::new(vec![vec![1.], vec![0.5], vec![0.]], input).collect::<Vec<_>>();
+        assert_eq!(output, vec![2.]);
+    }
+
+    #[test]
+    fn it_can_change_the_gains_while_playing() {
+        let gain = DynamicFloat::new(0.);
+        let gains = vec![vec![DynamicFloat::new(1.), gain.clone()]];
+        let mut matrix = ChannelMatrix::new(gains, [1., 1.].into_iter());
+
+        assert_eq!(matrix.by_ref().take(2).collect::<Vec<_>>(), vec![1., 0.]);
+
+        gain.set(0.5);
+        assert_eq!(matrix.collect::<Vec<_>>(), vec![1., 0.5]);
+    }
+
+    #[test]
+    fn it_pads_the_last_frame_with_silence() {
+        let input = [1., 2., 3.].into_iter();
+        let output = ChannelMatrix::new(vec![vec![1., 1.], vec![1., 1.]], input).collect::<Vec<_>>();
+        assert_eq!(output, vec![3., 3., 3., 3.]);
+    }
+}
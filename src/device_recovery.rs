@@ -0,0 +1,63 @@
+use crate::*;
+use crate::audio_mixer::{Inner, Output};
+use crate::mixer_error::ErrorHandler;
+use std::sync::mpsc::RecvTimeoutError;
+
+// What the mixer does when its output device disappears, e.g. when a USB
+// headset is unplugged. By default it reports the error and goes silent. With
+// DefaultDevice, the mix moves onto whichever device is now the default in the
+// same way as set_device, so sources that were playing carry on.
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RecoveryPolicy {
+    #[default]
+    Disabled,
+    DefaultDevice,
+}
+
+// If there's no default device to move to, try again this often.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+pub(crate) enum RecoveryCommand {
+    DeviceLost(usize),
+    Release(Sender<()>),
+    Stop,
+}
+
+// Streams can't be sent between threads so the one that replaces a lost stream
+// is built and kept on this thread until it is released, i.e. when the mixer
+// moves to another device or is dropped.
+pub(crate) fn spawn_recovery_thread(inner: Arc<Mutex<Inner>>, output: Arc<Mutex<Output>>, errors: Arc<ErrorHandler>, request: AudioMixerBuilder, receiver: Receiver<RecoveryCommand>) {
+    std::thread::spawn(move || {
+        let mut _stream = None;
+        let mut lost = false;
+
+        loop {
+            let command = if lost { receiver.recv_timeout(RETRY_INTERVAL) } else { receiver.recv().map_err(|_| RecvTimeoutError::Disconnected) };
+
+            match command {
+                Ok(RecoveryCommand::DeviceLost(generation)) => {
+                    // A stream can report errors more than once and only the
+                    // current stream's errors mean the mix has been lost.
+                    if generation != output.lock().unwrap().generation { continue; }
+                    lost = true;
+                },
+                Ok(RecoveryCommand::Release(reply)) => {
+                    _stream = None;
+                    lost = false;
+
+                    let _ = reply.send(());
+                    continue;
+                },
+                Ok(RecoveryCommand::Stop) | Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => {},
+            }
+
+            match AudioMixer::move_to_default_device(&inner, &output, &errors, &request) {
+                Ok(s) => { _stream = Some(s); lost = false; },
+                Err(MixerError::DeviceNotAvailable) => {},
+                Err(error) => errors.report(error),
+            }
+        }
+    });
+}
@@ -0,0 +1,134 @@
+use crate::*;
+
+// Converts float samples to 16-bit integers with TPDF (triangular) dither so
+// that quiet content fades into a constant, low noise floor instead of being
+// truncated into distortion. Noise shaping feeds the quantization error back
+// so that more of the noise is pushed up to high frequencies where it is less
+// audible, at the cost of a slightly louder noise floor overall.
+//
+// The mixer uses this for I16 and U16 devices if MixerOptions::dither is set.
+// It can also be used to convert an AudioFrame when writing 16-bit files.
+
+#[derive(Clone, Debug, Default)]
+pub struct DitherOptions {
+    pub noise_shaping: NoiseShaping,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NoiseShaping {
+    #[default]
+    None,
+    FirstOrder,
+    SecondOrder,
+}
+
+pub struct Dither {
+    feedback: [f32; 2],
+    channels: usize,
+    channel: usize,
+    errors: Vec<[f32; 2]>,
+    random: u32,
+}
+
+const SCALE: f32 = i16::MAX as f32;
+
+impl Dither {
+    pub fn new(options: &DitherOptions, channels: usize) -> Self {
+        let feedback = match options.noise_shaping {
+            NoiseShaping::None => [0., 0.],
+            NoiseShaping::FirstOrder => [1., 0.],
+            NoiseShaping::SecondOrder => [2., -1.],
+        };
+
+        Self { feedback, channels, channel: 0, errors: vec![[0.; 2]; channels], random: 0x9e37_79b9 }
+    }
+
+    // Samples are expected to be interleaved, starting on the first channel.
+    pub fn next_i16(&mut self, sample: f32) -> i16 {
+        let [e1, e2] = self.errors[self.channel];
+        let target = sample * SCALE - self.feedback[0] * e1 - self.feedback[1] * e2;
+
+        let dithered = target + self.random_lsb() + self.random_lsb();
+        let quantized = dithered.round().clamp(i16::MIN as f32, i16::MAX as f32);
+
+        // Clipping can produce a large error so limit how much is fed back.
+        let error = (quantized - target).clamp(-1.5, 1.5);
+        self.errors[self.channel] = [error, e1];
+        self.channel = (self.channel + 1) % self.channels;
+
+        quantized as i16
+    }
+
+    // Writes dithered samples in the output's format, e.g. u16. Float outputs
+    // are converted as normal since they don't need dither.
+    pub fn convert<S: Sample>(&mut self, samples: &[f32], out: &mut [S]) {
+        if let SampleFormat::F32 = S::FORMAT {
+            out.iter_mut().zip(samples).for_each(|(o, s)| *o = Sample::from(s));
+        } else {
+            out.iter_mut().zip(samples).for_each(|(o, s)| *o = Sample::from(&self.next_i16(*s)));
+        }
+    }
+
+    // The noise shaping history is per channel so it is cleared if they change.
+    pub(crate) fn set_channels(&mut self, channels: usize) {
+        self.channels = channels;
+        self.channel = 0;
+        self.errors = vec![[0.; 2]; channels];
+    }
+
+    // Returns a uniform value in [-0.5, 0.5) of the least significant bit. Two
+    // of these are summed to give a triangular distribution. This uses xorshift
+    // because it is fast, doesn't allocate and is plenty random for noise.
+    fn random_lsb(&mut self) -> f32 {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 17;
+        self.random ^= self.random << 5;
+
+        (self.random >> 8) as f32 / (1 << 24) as f32 - 0.5
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_dithers_quiet_signals_rather_than_truncating_them_to_silence() {
+        let mut dither = Dither::new(&DitherOptions::default(), 1);
+
+        // A signal a third of the least significant bit would be truncated to 0.
+        let quantized = (0..30000).map(|_| dither.next_i16(1. / 3. / SCALE) as f32).collect::<Vec<_>>();
+        let mean = quantized.iter().sum::<f32>() / quantized.len() as f32;
+
+        assert!(quantized.iter().all(|q| (-1. ..=2.).contains(q)));
+        assert!((mean - 1. / 3.).abs() < 0.02, "mean was {}", mean);
+    }
+
+    #[test]
+    fn it_pushes_the_noise_to_high_frequencies_when_noise_shaping() {
+        let low_frequency_noise = |noise_shaping| {
+            let mut dither = Dither::new(&DitherOptions { noise_shaping }, 1);
+            let errors = (0..30000).map(|_| dither.next_i16(0.1) as f32 - 0.1 * SCALE).collect::<Vec<_>>();
+
+            // Averaging neighboring samples is a crude low-pass filter.
+            errors.chunks(32).map(|c| (c.iter().sum::<f32>() / 32.).powi(2)).sum::<f32>()
+        };
+
+        let flat = low_frequency_noise(NoiseShaping::None);
+
+        assert!(low_frequency_noise(NoiseShaping::FirstOrder) < flat / 4.);
+        assert!(low_frequency_noise(NoiseShaping::SecondOrder) < flat / 4.);
+    }
+
+    #[test]
+    fn it_converts_to_the_output_format() {
+        let mut dither = Dither::new(&DitherOptions::default(), 2);
+        let mut out = [0_u16; 4];
+
+        // Unsigned samples are centered on the midpoint, give or take the dither.
+        dither.convert(&[0., 0., -1., -1.], &mut out);
+
+        assert!(out[..2].iter().all(|o| o.abs_diff(32768) <= 1));
+        assert!(out[2..].iter().all(|o| *o <= 2));
+    }
+}
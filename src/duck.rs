@@ -0,0 +1,144 @@
+use crate::*;
+
+// Turns a source down while a sidechain signal is loud, e.g. music while a
+// character is speaking. The sidechain is a level, usually from a Sidechain
+// adapter wrapped around another source or in a bus's effect chain, but any
+// MaybeDynamic<f32> works so it can also be driven by hand.
+//
+// Above the threshold, the source is turned down like a compressor with the
+// given ratio, but by no more than the depth. The gain moves towards its target
+// over the attack time when ducking and the release time when recovering. The
+// current gain can be read from another thread, e.g. to show it in a UI.
+
+pub struct Duck<L: MaybeDynamic<f32>, S: Iterator<Item=f32>> {
+    sidechain: L,
+    source: S,
+    channels: usize,
+    threshold: f32,
+    exponent: f32,
+    floor: f32,
+    attack: f32,
+    release: f32,
+    gain: DynamicFloat,
+    current: f32,
+    channel: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct DuckOptions {
+    // The sidechain level (as a linear peak) that ducking starts at.
+    pub threshold: f32,
+
+    // How many times louder than the threshold the sidechain must be for the
+    // source to be turned down by the same amount, e.g. 4 for 4:1.
+    pub ratio: f32,
+
+    // The most that the source is turned down by, e.g. 0.75 leaves it at 0.25.
+    pub depth: f32,
+
+    pub attack: Duration,
+    pub release: Duration,
+}
+
+impl<L: MaybeDynamic<f32>, S: Iterator<Item=f32>> Duck<L, S> {
+    pub fn new(sidechain: L, options: DuckOptions, channels: usize, sample_rate: usize, source: S) -> Self {
+        assert!(channels > 0, "The source must have at least one channel");
+
+        let coefficient = |time: Duration| (-1. / (time.as_secs_f32() * sample_rate as f32)).exp();
+
+        let exponent = 1. - 1. / options.ratio.max(1.);
+        let floor = 1. - options.depth.clamp(0., 1.);
+        let (attack, release) = (coefficient(options.attack), coefficient(options.release));
+
+        Self { sidechain, source, channels, threshold: options.threshold, exponent, floor, attack, release, gain: DynamicFloat::new(1.), current: 1., channel: 0 }
+    }
+
+    pub fn gain(&self) -> DynamicFloat {
+        self.gain.clone()
+    }
+
+    // The gain is updated once per frame so that every channel is ducked equally.
+    fn process(&mut self, sample: f32) -> f32 {
+        if self.channel == 0 {
+            let level = self.sidechain.get();
+
+            let target = if level > self.threshold {
+                (self.threshold / level).powf(self.exponent).max(self.floor)
+            } else {
+                1.
+            };
+
+            let coefficient = if target < self.current { self.attack } else { self.release };
+            self.current = target + (self.current - target) * coefficient;
+            self.gain.set(self.current);
+        }
+
+        self.channel = (self.channel + 1) % self.channels;
+        sample * self.current
+    }
+}
+
+impl Default for DuckOptions {
+    fn default() -> Self {
+        Self { threshold: 0.05, ratio: 4., depth: 0.75, attack: Duration::from_millis(20), release: Duration::from_millis(300) }
+    }
+}
+
+impl<L: MaybeDynamic<f32>, S: Iterator<Item=f32>> Iterator for Duck<L, S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.source.next().map(|s| self.process(s))
+    }
+}
+
+impl<L: MaybeDynamic<f32>, S: Iterator<Item=f32> + BlockSource> BlockSource for Duck<L, S> {
+    fn fill_block(&mut self, block: &mut [f32]) -> usize {
+        let written = self.source.fill_block(block);
+
+        block[..written].iter_mut().for_each(|s| *s = self.process(*s));
+        written
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn options(attack: Duration, release: Duration) -> DuckOptions {
+        DuckOptions { threshold: 0.1, ratio: 2., depth: 0.75, attack, release }
+    }
+
+    #[test]
+    fn it_leaves_the_source_alone_while_the_sidechain_is_below_the_threshold() {
+        let duck = Duck::new(0.05, options(Duration::ZERO, Duration::ZERO), 2, 10, [1., 0.5, -1., -0.5].into_iter());
+        assert_eq!(duck.collect::<Vec<_>>(), vec![1., 0.5, -1., -0.5]);
+    }
+
+    #[test]
+    fn it_turns_the_source_down_by_the_ratio_but_no_more_than_the_depth() {
+        // The sidechain is 4x the threshold so a 2:1 ratio halves the volume.
+        let duck = Duck::new(0.4, options(Duration::ZERO, Duration::ZERO), 1, 10, [1., 1.].into_iter());
+        assert_eq!(duck.collect::<Vec<_>>(), vec![0.5, 0.5]);
+
+        let duck = Duck::new(100., options(Duration::ZERO, Duration::ZERO), 1, 10, [1., 1.].into_iter());
+        assert_eq!(duck.collect::<Vec<_>>(), vec![0.25, 0.25]);
+    }
+
+    #[test]
+    fn it_moves_the_gain_over_the_attack_and_release_times() {
+        let level = DynamicFloat::new(100.);
+        let mut duck = Duck::new(level.clone(), options(Duration::from_secs(1), Duration::from_secs(1)), 2, 10, std::iter::repeat(1.));
+        let gain = duck.gain();
+
+        let ducking = duck.by_ref().step_by(2).take(30).collect::<Vec<_>>();
+        assert!(ducking.windows(2).all(|w| w[1] < w[0]));
+        assert!(ducking[0] > 0.9 && ducking[29] < 0.3);
+
+        level.set(0.);
+
+        let releasing = duck.by_ref().step_by(2).take(30).collect::<Vec<_>>();
+        assert!(releasing.windows(2).all(|w| w[1] > w[0]));
+        assert_eq!(gain.get(), releasing[29]);
+    }
+}
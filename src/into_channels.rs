@@ -1,3 +1,5 @@
+use crate::*;
+
 pub struct IntoChannels<S: Iterator<Item=f32>> {
     from: usize,
     to: usize,
@@ -5,20 +7,50 @@ pub struct IntoChannels<S: Iterator<Item=f32>> {
     strategy: fn(&mut Self) -> Option<f32>,
     counter: usize,
     previous: f32,
+    matrix: Vec<f32>,
+    frame: Vec<f32>,
+    output: Vec<f32>,
 }
 
 impl<S: Iterator<Item=f32>> IntoChannels<S> {
     pub fn new(from: usize, to: usize, source: S) -> Self {
-        let strategy = match (from, to) {
+        let strategy = Self::strategy(from, to);
+
+        Self { from, to, source, strategy, counter: 0, previous: -1., matrix: vec![], frame: vec![], output: vec![] }
+    }
+
+    fn strategy(from: usize, to: usize) -> fn(&mut Self) -> Option<f32> {
+        match (from, to) {
             (a, b) if a == b => Self::noop,
             (1, _)           => Self::duplicate,
             (_, 1)           => Self::combine,
             (a, b) if a < b  => Self::pad,
             (a, b) if a > b  => Self::discard,
             _                => unreachable!(),
-        };
+        }
+    }
+
+    // Converts between speaker layouts with standard downmix and upmix gains,
+    // e.g. so that the center channel isn't lost when playing 5.1 in stereo.
+    // Set normalize to scale the gains down so that the output can't clip.
+    pub fn with_layouts(from: ChannelLayout, to: ChannelLayout, normalize: bool, source: S) -> Self {
+        let (from_channels, to_channels) = (from.channels(), to.channels());
+        let strategy = if from == to { Self::noop } else { Self::mix } as fn(&mut Self) -> Option<f32>;
 
-        Self { from, to, source, strategy, counter: 0, previous: -1. }
+        let matrix = from.matrix(to, normalize);
+        let (frame, output) = (vec![0.; from_channels], vec![0.; to_channels]);
+
+        Self { from: from_channels, to: to_channels, source, strategy, counter: 0, previous: -1., matrix, frame, output }
+    }
+
+    // Changes the number of input channels in place, e.g. when the source's
+    // format changes. This doesn't apply to conversions between layouts.
+    pub(crate) fn set_from(&mut self, from: usize) {
+        (self.from, self.strategy, self.counter) = (from, Self::strategy(from, self.to), 0);
+    }
+
+    pub(crate) fn source_mut(&mut self) -> &mut S {
+        &mut self.source
     }
 
     // Don't incur any performance overhead when not changing channels.
@@ -61,6 +93,28 @@ impl<S: Iterator<Item=f32>> IntoChannels<S> {
         sample
     }
 
+    // Mix each frame of input channels into output channels with the matrix.
+    fn mix(&mut self) -> Option<f32> {
+        if self.counter == 0 {
+            self.frame[0] = self.source.next()?;
+
+            for sample in &mut self.frame[1..] {
+                *sample = self.source.next().unwrap_or(0.);
+            }
+
+            self.output.fill(0.);
+
+            for (sample, gains) in self.frame.iter().zip(self.matrix.chunks(self.to)) {
+                self.output.iter_mut().zip(gains).for_each(|(o, g)| *o += g * sample);
+            }
+        }
+
+        let sample = self.output[self.counter];
+        self.counter = (self.counter + 1) % self.to;
+
+        Some(sample)
+    }
+
     // Discard additional input channels (e.g. from=3, to=2).
     fn discard(&mut self) -> Option<f32> {
         loop {
@@ -81,6 +135,12 @@ impl<S: Iterator<Item=f32>> Iterator for IntoChannels<S> {
     }
 }
 
+impl<S: Iterator<Item=f32>> BlockSource for IntoChannels<S> {
+    fn fill_block(&mut self, block: &mut [f32]) -> usize {
+        fill_from_iterator(self, block)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -147,4 +207,29 @@ mod test {
         let output = IntoChannels::new(4, 2, input).collect::<Vec<_>>();
         assert_eq!(output, vec![1., 2., 5.]);
     }
+
+    #[test]
+    fn it_downmixes_surround_sound_without_losing_the_center_channel() {
+        let input = [1., 0., 1., 1., 1., 0.].into_iter();
+        let output = IntoChannels::with_layouts(ChannelLayout::Surround51, ChannelLayout::Stereo, false, input).collect::<Vec<_>>();
+
+        let gain = std::f32::consts::FRAC_1_SQRT_2;
+        assert_eq!(output, vec![1. + gain + gain, gain]);
+    }
+
+    #[test]
+    fn it_derives_a_center_channel_when_upmixing_stereo() {
+        let input = [1., 0., 0.5, 0.5].into_iter();
+        let output = IntoChannels::with_layouts(ChannelLayout::Stereo, ChannelLayout::Surround51, false, input).collect::<Vec<_>>();
+
+        assert_eq!(output, vec![1., 0., 0.5, 0., 0., 0., 0.5, 0.5, 0.5, 0., 0., 0.]);
+    }
+
+    #[test]
+    fn it_can_normalize_the_gains_so_that_the_output_does_not_clip() {
+        let input = [1., 1., 1., 1.].into_iter();
+        let output = IntoChannels::with_layouts(ChannelLayout::Stereo, ChannelLayout::Mono, true, input).collect::<Vec<_>>();
+
+        assert_eq!(output, vec![1., 1.]);
+    }
 }
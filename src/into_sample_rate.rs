@@ -7,6 +7,9 @@ pub struct IntoSampleRate<S: Iterator<Item=f32>> {
     strategy: fn(&mut Self) -> Option<f32>,
     position: f64,
     after_index: usize,
+    delay: f64,
+    scale: f64,
+    glide: f64,
 
     // These fields are only used in sample_based_linear_interpolation.
     sample_before: f32,
@@ -17,30 +20,148 @@ pub struct IntoSampleRate<S: Iterator<Item=f32>> {
     frame_after: Vec<f32>,
     output_samples: Vec<f32>,
     sample_count: usize,
+
+    // These fields are only used in kernel_interpolation.
+    quality: ResampleQuality,
+    history: Vec<f32>,
+    weights: Vec<f32>,
+    cutoff: f32,
+    phases: usize,
+    table: Vec<f32>,
+    compensate_edges: bool,
+    source_frames: Option<usize>,
+    rates: (usize, usize),
+    frame_count: usize,
+}
+
+// Linear is the cheapest and is the default. Cubic (Catmull-Rom) is smoother
+// for a little more work. The sinc modes low-pass filter the signal so that it
+// doesn't alias when downsampling or pitching up, which keeps the top end clear.
+// More taps give a sharper filter but each output frame costs a multiply per tap.
+//
+// Sinc computes its filter for every frame so it follows a dynamic input rate
+// exactly. Polyphase precomputes a table of filters for fixed ratios, e.g.
+// 44100Hz to 48000Hz, which is much faster. Its filters can't follow a rate
+// that changes so Sinc is used instead for dynamic rates and speeds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResampleQuality {
+    #[default]
+    Linear,
+    Cubic,
+    Sinc { taps: usize },
+    Polyphase { taps: usize },
+}
+
+// By default, the output starts by interpolating from silence and stops when
+// the source runs out, so it lags the source by half of the filter's taps and
+// is missing the last few frames. With compensate_edges, the filter is primed
+// with the first frame and the last frame is held to flush out the tail. The
+// output then lines up with the source and has len * to / from frames, so that
+// resampled loops can be spliced together without clicks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ResampleOptions {
+    pub quality: ResampleQuality,
+    pub compensate_edges: bool,
+
+    // How long it takes to (mostly) reach a new rate when it changes, rather
+    // than jumping straight to it. This only applies to dynamic rates.
+    pub glide: Duration,
 }
 
+// The most filters that the polyphase table holds. Ratios that need more than
+// this use the closest filter, which is at most 1/2048th of a frame away.
+const MAX_PHASES: usize = 1024;
+
 pub enum SampleRates {
-    Static { scale: f32 },
+    Static { scale: f64 },
     Dynamic { from: DynamicUsize, to: f32 },
+    Speed { scale: f64, speed: DynamicFloat },
 }
 
 impl<S: Iterator<Item=f32>> IntoSampleRate<S> {
     pub fn new<T: IntoSampleRates>(from: T, to: usize, channels: usize, source: S) -> Self {
+        Self::with_quality(from, to, channels, ResampleQuality::Linear, source)
+    }
+
+    pub fn with_quality<T: IntoSampleRates>(from: T, to: usize, channels: usize, quality: ResampleQuality, source: S) -> Self {
+        Self::with_options(from, to, channels, ResampleOptions { quality, ..Default::default() }, source)
+    }
+
+    pub fn with_options<T: IntoSampleRates>(from: T, to: usize, channels: usize, options: ResampleOptions, source: S) -> Self {
         let from_rate = from.get();
-        let sample_rates = from.sample_rates(to);
+        Self::build(from_rate, from.sample_rates(to), to, channels, options, source)
+    }
+
+    // Plays the source faster or slower by a ratio that can change while it
+    // plays, e.g. 1.5 is 50% faster and higher pitched and 0.5 is slow-motion.
+    // The source is also converted from its sample rate to the output's.
+    pub fn with_speed(from: usize, to: usize, channels: usize, speed: DynamicFloat, options: ResampleOptions, source: S) -> Self {
+        let sample_rates = SampleRates::Speed { scale: from as f64 / to as f64, speed };
+        Self::build(from, sample_rates, to, channels, options, source)
+    }
+
+    fn build(from_rate: usize, sample_rates: SampleRates, to: usize, channels: usize, options: ResampleOptions, source: S) -> Self {
+        let compensate_edges = options.compensate_edges;
+
+        // The table's cutoff is fixed so it would alias when pitching up.
+        let quality = match (options.quality, sample_rates.is_dynamic()) {
+            (ResampleQuality::Polyphase { taps }, true) => ResampleQuality::Sinc { taps },
+            (quality, _) => quality,
+        };
+
+        // Edges are only compensated by the kernel so it also handles Linear.
+        let strategy = match (from_rate, to, channels, &sample_rates, quality, compensate_edges) {
+            (a, b, _, SampleRates::Static { .. }, _, _) if a == b => Self::noop,
+            (_, _, 1, _, ResampleQuality::Linear, false) => Self::sample_based_linear_interpolation,
+            (_, _, _, _, ResampleQuality::Linear, false) => Self::frame_based_linear_interpolation,
+            (_, _, _, _, _, _) => Self::kernel_interpolation,
+        };
+
+        let noop = from_rate == to && !sample_rates.is_dynamic();
 
-        let strategy = match (from_rate, to, channels, &sample_rates) {
-            (a, b, _, SampleRates::Static { .. }) if a == b => Self::noop,
-            (_, _, 1, _) => Self::sample_based_linear_interpolation,
-            (_, _, _, _) => Self::frame_based_linear_interpolation,
+        // Filters need an even number of taps so that the output falls between
+        // the middle two. Only sinc filters are allowed more than four.
+        let taps = match quality {
+            ResampleQuality::Linear => 2,
+            ResampleQuality::Cubic => 4,
+            ResampleQuality::Sinc { taps } | ResampleQuality::Polyphase { taps } => taps.max(4) / 2 * 2,
         };
 
+        // The output is between the middle two taps, i.e. half of them behind.
+        let half = (taps / 2) as f64;
+        let delay = if noop || compensate_edges { 0. } else { half };
+
+        // Remove the frequencies that the output rate can't represent.
+        let scale = sample_rates.scale();
+        let cutoff = (1. / scale as f32).min(1.);
+
+        let phases = match quality {
+            ResampleQuality::Polyphase { .. } => (to / gcd(from_rate, to)).min(MAX_PHASES),
+            _ => 0,
+        };
+
+        let glide_frames = options.glide.as_secs_f64() * to as f64;
+        let glide = if glide_frames > 0. { (-1. / glide_frames).exp() } else { 0. };
+
+        let (mut weights, mut table) = (vec![0.; taps], vec![]);
+
+        // The table has a filter for t = 1 as well so that rounding up is safe.
+        if phases > 0 {
+            for phase in 0..=phases {
+                sinc_weights(&mut weights, phase as f32 / phases as f32, cutoff);
+                table.extend_from_slice(&weights);
+            }
+        }
+
         Self {
             sample_rates,
             channels,
             source,
             strategy,
-            position: 0.,
+            position: if compensate_edges { half } else { 0. },
+            delay,
+            scale,
+            glide,
             sample_before: 0.,
             sample_after: 0.,
             frame_before: vec![0.; channels],
@@ -48,21 +169,56 @@ impl<S: Iterator<Item=f32>> IntoSampleRate<S> {
             after_index: 0,
             sample_count: 0,
             output_samples: vec![0.; channels],
+            quality,
+            history: vec![0.; taps * channels],
+            weights,
+            cutoff,
+            phases,
+            table,
+            compensate_edges,
+            source_frames: None,
+            rates: (from_rate, to),
+            frame_count: 0,
         }
     }
 
+    // How far the output lags behind the source, in output frames. This is half
+    // of the filter's taps (converted to the output rate) unless the edges are
+    // compensated or no conversion is needed. It is infinite while a speed of
+    // zero holds the output on one frame.
+    pub fn group_delay(&self) -> f64 {
+        if self.delay == 0. { 0. } else { self.delay / self.scale }
+    }
+
+    // Moves the scale towards the current rates, gliding if a glide time was set.
+    fn step(&mut self) -> f64 {
+        let target = self.sample_rates.scale();
+        self.scale = target + (self.scale - target) * self.glide;
+        self.scale
+    }
+
+    // If a dynamic input rate matches the output rate, the linear modes copy the
+    // samples rather than interpolating them. The position is rounded to a whole
+    // frame so each output frame is exactly a source frame. It is one frame
+    // behind and the source's last frame is left out, the same as the
+    // interpolated output, so that nothing jumps when the rate changes.
+    fn step_or_passthrough(&mut self) -> (f64, bool) {
+        let scale = self.step();
+        let passthrough = scale == 1.;
+
+        if passthrough { self.position = self.position.round(); }
+        (scale, passthrough)
+    }
+
     fn noop(&mut self) -> Option<f32> {
         self.source.next()
     }
 
     // Linearly interpolate between neighboring samples to derive samples at the
     // faster/slower sample rate. This produces pretty good results and isn't too
-    // difficult to implement but won't be as good as others, e.g Sinc interpolation.
+    // difficult to implement but won't be as good as the other ResampleQuality modes.
     fn sample_based_linear_interpolation(&mut self) -> Option<f32> {
-        // If the sample rates match, we don't need to do any conversion.
-        // Keep hold of from rather than loading the atomic again later.
-        let (noop, from) = self.sample_rates.noop();
-        if noop { return self.source.next(); }
+        let (scale, passthrough) = self.step_or_passthrough();
 
         // Calculate the index in the source iterator for the current sample count.
         // This will probably be somewhere between two indexes (the ratio t).
@@ -83,9 +239,9 @@ impl<S: Iterator<Item=f32>> IntoSampleRate<S> {
 
         // Linearly interpolate between the neighboring samples using the ratio t.
         let delta = self.sample_after - self.sample_before;
-        let sample = self.sample_before + t * delta;
+        let sample = if passthrough { self.sample_before } else { self.sample_before + t * delta };
 
-        self.position += self.sample_rates.scale(from) as f64;
+        self.position += scale;
         Some(sample)
     }
 
@@ -93,18 +249,13 @@ impl<S: Iterator<Item=f32>> IntoSampleRate<S> {
     // frame-by-frame (all channels at once) and therefore needs to use vectors
     // and pre-compute some values. It'll be slower than the one above.
     fn frame_based_linear_interpolation(&mut self) -> Option<f32> {
+        let channel = self.sample_count % self.channels;
         self.sample_count += 1;
 
-        // If the sample rates match, we don't need to do any conversion.
-        // Keep hold of from rather than loading the atomic again later.
-        let (noop, from) = self.sample_rates.noop();
-        if noop { return self.source.next(); }
-
         // Return the samples from the output_samples buffer (computed below).
-        // Handle the case when we transition from noop and the output_samples buffer is empty.
-        let channel = self.sample_count % self.channels;
-        if channel != 0 { return self.output_samples.get(channel).copied().or(Some(0.)); }
+        if channel != 0 { return Some(self.output_samples[channel]); }
 
+        let (scale, passthrough) = self.step_or_passthrough();
         let (index, t) = (self.position as usize, self.position.fract() as f32);
 
         while index >= self.after_index {
@@ -122,14 +273,147 @@ impl<S: Iterator<Item=f32>> IntoSampleRate<S> {
         }
 
         // Store these values in output_samples which acts as a kind of buffer.
-        for i in 0..self.channels {
-            let delta = self.frame_after[i] - self.frame_before[i];
-            self.output_samples[i] = self.frame_before[i] + t * delta;
+        if passthrough {
+            self.output_samples.copy_from_slice(&self.frame_before);
+        } else {
+            for i in 0..self.channels {
+                let delta = self.frame_after[i] - self.frame_before[i];
+                self.output_samples[i] = self.frame_before[i] + t * delta;
+            }
         }
 
-        self.position += self.sample_rates.scale(from) as f64;
+        self.position += scale;
         Some(self.output_samples[0])
     }
+
+    // This generalizes frame_based_linear_interpolation to filters with more
+    // taps. The history holds the last frames that were read from the source and
+    // each output frame is a weighted sum of them. Like linear interpolation, the
+    // output is between the middle two frames so it lags by half of the taps.
+    fn kernel_interpolation(&mut self) -> Option<f32> {
+        let channel = self.sample_count % self.channels;
+        self.sample_count += 1;
+
+        if channel != 0 { return Some(self.output_samples[channel]); }
+
+        let scale = self.step();
+        let (index, t) = (self.position as usize, self.position.fract() as f32);
+
+        while index >= self.after_index {
+            self.read_frame()?;
+            self.after_index += 1;
+        }
+
+        // The output has caught up with the end of the source. With fixed rates
+        // that is after len * to / from frames (rounded up), which is counted
+        // exactly rather than relying on the position that accumulates rounding
+        // error over a long source.
+        if let Some(frames) = self.source_frames {
+            let half = (self.weights.len() / 2) as f64;
+            let (from, to) = self.rates;
+
+            let end = match self.sample_rates {
+                SampleRates::Static { .. } => self.frame_count >= (frames * to).div_ceil(from),
+                _ => self.position - half >= frames as f64,
+            };
+
+            if end { return None; }
+        }
+
+        match self.quality {
+            ResampleQuality::Linear => linear_weights(&mut self.weights, t),
+            ResampleQuality::Cubic => cubic_weights(&mut self.weights, t),
+            ResampleQuality::Polyphase { .. } => {
+                let (phase, taps) = ((t * self.phases as f32).round() as usize, self.weights.len());
+                self.weights.copy_from_slice(&self.table[phase * taps..(phase + 1) * taps]);
+            },
+            _ => {
+                let cutoff = if self.sample_rates.is_dynamic() { (1. / scale as f32).min(1.) } else { self.cutoff };
+                sinc_weights(&mut self.weights, t, cutoff);
+            },
+        }
+
+        for (i, output) in self.output_samples.iter_mut().enumerate() {
+            let frames = self.history[i..].iter().step_by(self.channels);
+            *output = frames.zip(&self.weights).map(|(h, w)| h * w).sum();
+        }
+
+        self.position += scale;
+        self.frame_count += 1;
+        Some(self.output_samples[0])
+    }
+
+    // Shifts the next frame from the source into the history. When compensating
+    // edges, the first frame is copied into the whole history and the last whole
+    // frame is held once the source runs out. A partial frame at the end is dropped.
+    fn read_frame(&mut self) -> Option<()> {
+        let (len, channels) = (self.history.len(), self.channels);
+        self.history.copy_within(channels.., 0);
+
+        if self.source_frames.is_some() { return Some(()); }
+
+        for i in 0..channels {
+            match (self.source.next(), self.compensate_edges) {
+                (Some(sample), _) => self.history[len - channels + i] = sample,
+                (None, true) => {
+                    self.history.copy_within(len - 2 * channels..len - channels, len - channels);
+                    self.source_frames = Some(self.after_index);
+                    return Some(());
+                },
+                (None, false) => return None,
+            }
+        }
+
+        if self.compensate_edges && self.after_index == 0 {
+            for frame in (0..len - channels).step_by(channels) {
+                self.history.copy_within(len - channels.., frame);
+            }
+        }
+
+        Some(())
+    }
+}
+
+fn linear_weights(weights: &mut [f32], t: f32) {
+    weights[0] = 1. - t;
+    weights[1] = t;
+}
+
+// Catmull-Rom weights for the four frames around t, which is between the
+// middle two. It passes through the frames exactly, like linear interpolation.
+fn cubic_weights(weights: &mut [f32], t: f32) {
+    let (t2, t3) = (t * t, t * t * t);
+
+    weights[0] = (-t3 + 2. * t2 - t) / 2.;
+    weights[1] = (3. * t3 - 5. * t2 + 2.) / 2.;
+    weights[2] = (-3. * t3 + 4. * t2 + t) / 2.;
+    weights[3] = (t3 - t2) / 2.;
+}
+
+// A low-pass filter at the cutoff (as a fraction of the input's Nyquist rate),
+// windowed with a Blackman window to the number of weights. The weights are
+// normalized so that a constant signal passes through at the same level.
+fn sinc_weights(weights: &mut [f32], t: f32, cutoff: f32) {
+    let half = (weights.len() / 2) as f32;
+
+    for (k, weight) in weights.iter_mut().enumerate() {
+        let distance = k as f32 - (half - 1.) - t;
+
+        let x = PI * cutoff * distance;
+        let sinc = if x.abs() < 1e-6 { 1. } else { x.sin() / x };
+
+        let w = PI * distance / half;
+        let window = if distance.abs() >= half { 0. } else { 0.42 + 0.5 * w.cos() + 0.08 * (2. * w).cos() };
+
+        *weight = sinc * window;
+    }
+
+    let sum = weights.iter().sum::<f32>();
+    weights.iter_mut().for_each(|w| *w /= sum);
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 { a.max(1) } else { gcd(b, a % b) }
 }
 
 impl<S: Iterator<Item=f32>> Iterator for IntoSampleRate<S> {
@@ -140,18 +424,24 @@ impl<S: Iterator<Item=f32>> Iterator for IntoSampleRate<S> {
     }
 }
 
+impl<S: Iterator<Item=f32>> BlockSource for IntoSampleRate<S> {
+    fn fill_block(&mut self, block: &mut [f32]) -> usize {
+        fill_from_iterator(self, block)
+    }
+}
+
 impl SampleRates {
-    pub fn noop(&self) -> (bool, f32) {
-        match self {
-            Self::Static { .. } => (false, 0.),
-            Self::Dynamic { from, to } => { let f = from.get() as f32; (f == *to, f) },
-        }
+    pub fn is_dynamic(&self) -> bool {
+        !matches!(self, Self::Static { .. })
     }
 
-    pub fn scale(&self, from: f32) -> f32 {
+    // How many input frames to move forward per output frame. The speed can't
+    // go backwards but it can be zero, which holds the current frame.
+    pub fn scale(&self) -> f64 {
         match self {
             Self::Static { scale } => *scale,
-            Self::Dynamic { to, .. } => from / *to,
+            Self::Dynamic { from, to } => (from.get() as f32 / *to) as f64,
+            Self::Speed { scale, speed } => *scale * speed.get().max(0.) as f64,
         }
     }
 }
@@ -162,7 +452,7 @@ pub trait IntoSampleRates: MaybeDynamic<usize> {
 
 impl IntoSampleRates for usize {
     fn sample_rates(self, to: usize) -> SampleRates {
-        SampleRates::Static { scale: self as f32 / to as f32 }
+        SampleRates::Static { scale: self as f64 / to as f64 }
     }
 }
 
@@ -187,7 +477,8 @@ mod test {
         // and don't actually produce the very last sample, which isn't ideal.
         //
         // In practice, this shouldn't matter because the audible duration of
-        // these samples for a 48KHz sound is ~0.000021 seconds.
+        // these samples for a 48KHz sound is ~0.000021 seconds. If it does, e.g.
+        // when splicing loops together, use ResampleOptions::compensate_edges.
 
         let input = [7., 5., 3.].into_iter();
         let output = IntoSampleRate::new(1, 2, 1, input).collect::<Vec<_>>();
@@ -219,6 +510,156 @@ mod test {
         assert_eq!(output, vec![0., 0., 1.5, 12., 3., 9., 4.5, 6., 6., 3.]);
     }
 
+    #[test]
+    fn it_reproduces_a_ramp_with_cubic_interpolation_after_a_delay_of_two_frames() {
+        let input = (0..20).map(|i| i as f32);
+        let output = IntoSampleRate::with_quality(1, 2, 1, ResampleQuality::Cubic, input).collect::<Vec<_>>();
+
+        let expected = (6..output.len()).map(|i| i as f32 / 2. - 2.).collect::<Vec<_>>();
+        assert_eq!(output[6..], expected);
+    }
+
+    #[test]
+    fn it_removes_frequencies_that_would_alias_when_downsampling_with_sinc() {
+        let tone = |channels| (0..4800 * channels).map(move |i| ((i / channels) as f32 * 2. * PI * 20000. / 48000.).sin());
+        let rms = |samples: Vec<f32>| (samples[200..].iter().map(|s| s * s).sum::<f32>() / (samples.len() - 200) as f32).sqrt();
+
+        let linear = IntoSampleRate::new(48000, 24000, 1, tone(1)).collect();
+        let sinc = IntoSampleRate::with_quality(48000, 24000, 1, ResampleQuality::Sinc { taps: 32 }, tone(1)).collect();
+        let stereo = IntoSampleRate::with_quality(48000, 24000, 2, ResampleQuality::Sinc { taps: 32 }, tone(2)).collect();
+
+        assert!(rms(linear) > 0.5);
+        assert!(rms(sinc) < 0.01);
+        assert!(rms(stereo) < 0.01);
+    }
+
+    #[test]
+    fn it_matches_the_sinc_output_with_a_polyphase_table_for_fixed_ratios() {
+        let input = (0..100).map(|i| (i as f32 * 0.3).sin());
+
+        let sinc = IntoSampleRate::with_quality(3, 2, 1, ResampleQuality::Sinc { taps: 16 }, input.clone());
+        let polyphase = IntoSampleRate::with_quality(3, 2, 1, ResampleQuality::Polyphase { taps: 16 }, input);
+
+        assert!(sinc.zip(polyphase).all(|(a, b)| (a - b).abs() < 1e-5));
+    }
+
+    #[test]
+    fn it_uses_sinc_filters_rather_than_a_polyphase_table_when_the_speed_can_change() {
+        let input = (0..100).map(|i| (i as f32 * 0.3).sin());
+        let options = |quality| ResampleOptions { quality, ..Default::default() };
+
+        let sinc = IntoSampleRate::with_speed(2, 2, 1, DynamicFloat::new(1.5), options(ResampleQuality::Sinc { taps: 16 }), input.clone());
+        let polyphase = IntoSampleRate::with_speed(2, 2, 1, DynamicFloat::new(1.5), options(ResampleQuality::Polyphase { taps: 16 }), input);
+
+        assert_eq!(sinc.collect::<Vec<_>>(), polyphase.collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn it_supports_a_dynamic_input_rate_with_the_kernel_modes() {
+        let input = [1.; 40].into_iter();
+        let input_rate = DynamicUsize::new(1);
+
+        let mut output = IntoSampleRate::with_quality(input_rate.clone(), 1, 1, ResampleQuality::Sinc { taps: 8 }, input);
+        assert_eq!(output.by_ref().take(10).count(), 10);
+
+        input_rate.set(2);
+        assert_eq!(output.by_ref().count(), 15);
+    }
+
+    #[test]
+    fn it_lines_up_with_the_source_and_flushes_the_tail_when_compensating_edges() {
+        let options = ResampleOptions { compensate_edges: true, ..Default::default() };
+
+        let input = [1., 2., 3.].into_iter();
+        let output = IntoSampleRate::with_options(1, 2, 1, options, input).collect::<Vec<_>>();
+        assert_eq!(output, vec![1., 1.5, 2., 2.5, 3., 3.]);
+
+        let input = [1., 7., 2., 5., 3., 3.].into_iter();
+        let output = IntoSampleRate::with_options(1, 2, 2, options, input).collect::<Vec<_>>();
+        assert_eq!(output, vec![1., 7., 1.5, 6., 2., 5., 2.5, 4., 3., 3., 3., 3.]);
+
+        let input = [1., 2., 3., 4., 5., 6., 7.].into_iter();
+        let output = IntoSampleRate::with_options(3, 2, 1, options, input).collect::<Vec<_>>();
+        assert_eq!(output, vec![1., 2.5, 4., 5.5, 7.]);
+    }
+
+    #[test]
+    fn it_outputs_len_times_to_over_from_frames_for_every_quality_when_compensating_edges() {
+        for quality in [ResampleQuality::Linear, ResampleQuality::Cubic, ResampleQuality::Sinc { taps: 16 }, ResampleQuality::Polyphase { taps: 16 }] {
+            let options = ResampleOptions { quality, compensate_edges: true, ..Default::default() };
+
+            let input = [0.5; 441 * 2].into_iter();
+            let output = IntoSampleRate::with_options(44100, 48000, 2, options, input).collect::<Vec<_>>();
+
+            assert_eq!(output.len(), 480 * 2);
+            assert!(output.iter().all(|s| (s - 0.5).abs() < 1e-3), "{:?}", quality);
+        }
+    }
+
+    #[test]
+    fn it_drops_a_partial_final_frame_and_counts_the_output_frames_exactly() {
+        let options = ResampleOptions { compensate_edges: true, ..Default::default() };
+
+        let input = [1., 7., 2., 5., 3.].into_iter();
+        let output = IntoSampleRate::with_options(1, 2, 2, options, input).collect::<Vec<_>>();
+        assert_eq!(output, vec![1., 7., 1.5, 6., 2., 5., 2., 5.]);
+
+        let input = std::iter::repeat_n(0., 44100 * 3 + 7);
+        assert_eq!(IntoSampleRate::with_options(44100, 48000, 1, options, input).count(), 144008);
+    }
+
+    #[test]
+    fn it_reports_the_group_delay_in_output_frames() {
+        let sinc = ResampleOptions { quality: ResampleQuality::Sinc { taps: 32 }, ..Default::default() };
+        let compensated = ResampleOptions { compensate_edges: true, ..sinc };
+
+        assert_eq!(IntoSampleRate::new(1, 2, 1, std::iter::empty()).group_delay(), 2.);
+        assert_eq!(IntoSampleRate::with_options(2, 1, 1, sinc, std::iter::empty()).group_delay(), 8.);
+        assert_eq!(IntoSampleRate::with_options(2, 1, 1, compensated, std::iter::empty()).group_delay(), 0.);
+        assert_eq!(IntoSampleRate::new(2, 2, 1, std::iter::empty()).group_delay(), 0.);
+
+        let stopped = |options| IntoSampleRate::with_speed(2, 1, 1, DynamicFloat::new(0.), options, std::iter::empty()).group_delay();
+        assert_eq!(stopped(sinc), f64::INFINITY);
+        assert_eq!(stopped(compensated), 0.);
+    }
+
+    #[test]
+    fn it_plays_the_source_at_a_fractional_speed() {
+        let speed = DynamicFloat::new(0.5);
+        let input = [1., 2., 3., 4.].into_iter();
+        let output = IntoSampleRate::with_speed(1, 1, 1, speed, ResampleOptions::default(), input).collect::<Vec<_>>();
+        assert_eq!(output, vec![0., 0.5, 1., 1.5, 2., 2.5, 3., 3.5]);
+
+        let speed = DynamicFloat::new(1.5);
+        let options = ResampleOptions { compensate_edges: true, ..Default::default() };
+        let output = IntoSampleRate::with_speed(2, 2, 2, speed, options, (0..20).map(|i| (i / 2) as f32)).collect::<Vec<_>>();
+        assert_eq!(output, vec![0., 0., 1.5, 1.5, 3., 3., 4.5, 4.5, 6., 6., 7.5, 7.5, 9., 9.]);
+    }
+
+    #[test]
+    fn it_glides_to_a_new_speed_rather_than_jumping_to_it() {
+        let speed = DynamicFloat::new(1.);
+        let options = ResampleOptions { compensate_edges: true, glide: Duration::from_secs(1), ..Default::default() };
+        let mut output = IntoSampleRate::with_speed(10, 10, 1, speed.clone(), options, (0..1000).map(|i| i as f32));
+
+        assert_eq!(output.by_ref().take(3).collect::<Vec<_>>(), vec![0., 1., 2.]);
+        speed.set(2.);
+
+        // The source is a ramp so the difference between outputs is the speed.
+        let output = output.take(40).collect::<Vec<_>>();
+        let steps = output.windows(2).map(|w| w[1] - w[0]).collect::<Vec<_>>();
+
+        assert!(steps.windows(2).all(|w| w[1] >= w[0]));
+        assert!(steps[0] < 1.2 && steps[38] > 1.9);
+    }
+
+    #[test]
+    fn it_copies_the_samples_one_frame_behind_while_a_dynamic_rate_matches_the_output_rate() {
+        let input = [0.3, 0.7, 0.1, 0.9, 0.2, 0.8].into_iter();
+        let output = IntoSampleRate::new(DynamicUsize::new(44100), 44100, 2, input).collect::<Vec<_>>();
+        assert_eq!(output, vec![0., 0., 0.3, 0.7, 0.1, 0.9]);
+    }
+
     #[test]
     fn it_can_dynamically_change_the_input_rate_to_control_the_pitch() {
         let input = [1., 2., 3., 4., 5., 6., 7., 8.].into_iter();
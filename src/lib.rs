@@ -3,35 +3,77 @@
 
 mod adjust_balance;
 mod adjust_volume;
+mod audio_input;
 mod audio_mixer;
+mod audio_mixer_builder;
 mod audio_recorder;
+mod block_source;
+mod bus;
+mod channel_layout;
+mod channel_matrix;
+mod device_recovery;
+mod dither;
+mod duck;
 mod dynamic_controls;
 mod into_channels;
 mod into_sample_rate;
 mod low_pass_coefficients;
 mod low_pass_filter;
+mod master_stage;
+mod meter;
+mod mirror;
+mod mixer_error;
+mod mixer_event;
+mod mixer_options;
 mod pausable_audio;
 mod pause_when_muted;
+mod playback_clock;
 mod reusable_buffer;
+mod ring_buffer;
+mod sidechain;
 mod skip_when_muted;
+mod source_handle;
+mod source_options;
 mod stop_when_muted;
 mod stoppable_audio;
+mod voice_limit;
+mod waiting;
 
 pub use adjust_balance::AdjustBalance;
 pub use adjust_volume::AdjustVolume;
+pub use audio_input::AudioInput;
 pub use audio_mixer::AudioMixer;
+pub use audio_mixer_builder::{AudioMixerBuilder, OutputConfig};
 pub use audio_recorder::{AudioRecorder, AudioFrame};
+pub use block_source::{BlockSource, FromBlocks, IntoBlocks, fill_from_iterator};
+pub use bus::{Bus, BusInput};
+pub use channel_layout::ChannelLayout;
+pub use channel_matrix::ChannelMatrix;
+pub use device_recovery::RecoveryPolicy;
+pub use dither::{Dither, DitherOptions, NoiseShaping};
+pub use duck::{Duck, DuckOptions};
 pub use dynamic_controls::{DynamicBool, DynamicUsize, DynamicFloat, MaybeDynamic};
 pub use into_channels::IntoChannels;
-pub use into_sample_rate::IntoSampleRate;
+pub use into_sample_rate::{IntoSampleRate, ResampleOptions, ResampleQuality};
 pub use low_pass_coefficients::LowPassCoefficients;
 pub use low_pass_filter::LowPassFilter;
+pub use master_stage::{MasterOptions, LimiterOptions};
+pub use meter::{Meter, MeterLevels};
+pub use mirror::{Mirror, MirrorOptions};
+pub use mixer_error::MixerError;
+pub use mixer_event::MixerEvent;
+pub use mixer_options::MixerOptions;
 pub use pausable_audio::PausableAudio;
 pub use pause_when_muted::PauseWhenMuted;
 pub use reusable_buffer::ReusableBuffer;
+pub use sidechain::Sidechain;
 pub use skip_when_muted::SkipWhenMuted;
+pub use source_handle::SourceHandle;
+pub use source_options::SourceOptions;
 pub use stop_when_muted::StopWhenMuted;
 pub use stoppable_audio::StoppableAudio;
+pub use voice_limit::{VoiceLimit, StealingPolicy};
+pub use waiting::WaitFuture;
 pub use cpal;
 
 #[cfg(feature = "ogg")] mod ogg_decoder;
@@ -42,17 +84,20 @@ pub use cpal;
 #[cfg(feature = "wav")] pub use wav_decoder::*;
 #[cfg(feature = "wav")] pub use hound;
 
+use std::any::Any;
 use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::f32::consts::PI;
 use std::mem::{swap, transmute};
+use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::sync::{Arc, Mutex};
-use std::thread::sleep;
-use std::time::Duration;
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender, TrySendError};
+use std::time::{Duration, Instant};
 
 use atomic_float::AtomicF32;
 
-use cpal::{Stream, SupportedStreamConfig, DefaultStreamConfigError};
+use cpal::{Stream, StreamConfig, SupportedStreamConfig, DefaultStreamConfigError};
 use cpal::{available_hosts, default_host, host_from_id};
 use cpal::{Device, Sample, SampleFormat, OutputCallbackInfo, StreamInstant};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
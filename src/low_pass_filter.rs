@@ -46,21 +46,51 @@ impl<S: Iterator<Item=f32>, F: M, C: M> Iterator for LowPassFilter<S, F, C> {
         if let Some(coefficients) = scoped[self.threshold_frequency.get()] {
             let channel = self.counter % self.channels.get();
 
-            let [b0, b1, b2, a1, a2] = coefficients;
-            let [x_n1, x_n2, y_n1, y_n2] = &mut self.previous[channel];
-
-            let output = b0 * sample + b1 * *x_n1 + b2 * *x_n2 - a1 * *y_n1 - a2 * *y_n2;
-
-            *y_n2 = *y_n1;
-            *x_n2 = *x_n1;
-            *y_n1 = output;
-            *x_n1 = sample;
-
             self.counter += 1;
-            Some(output)
+            Some(self.filter(sample, coefficients, channel))
         } else {
             self.counter += 1;
             Some(sample) // noop
         }
     }
 }
+
+impl<S: Iterator<Item=f32>, F: M, C: M> LowPassFilter<S, F, C> {
+    fn filter(&mut self, sample: f32, coefficients: [f32; 5], channel: usize) -> f32 {
+        let [b0, b1, b2, a1, a2] = coefficients;
+        let [x_n1, x_n2, y_n1, y_n2] = &mut self.previous[channel];
+
+        let output = b0 * sample + b1 * *x_n1 + b2 * *x_n2 - a1 * *y_n1 - a2 * *y_n2;
+
+        *y_n2 = *y_n1;
+        *x_n2 = *x_n1;
+        *y_n1 = output;
+        *x_n1 = sample;
+
+        output
+    }
+}
+
+// The threshold frequency and channels are only read once per block.
+impl<S: Iterator<Item=f32> + BlockSource, F: M, C: M> BlockSource for LowPassFilter<S, F, C> {
+    fn fill_block(&mut self, block: &mut [f32]) -> usize {
+        let written = self.source.fill_block(block);
+
+        let threshold = self.threshold_frequency.get();
+        if threshold >= self.max { return written; }
+
+        let channels = self.channels.get();
+        let scoped = self.coefficients.for_sample_rate_index(self.index);
+
+        if let Some(coefficients) = scoped[threshold] {
+            for sample in block[..written].iter_mut() {
+                *sample = self.filter(*sample, coefficients, self.counter % channels);
+                self.counter += 1;
+            }
+        } else {
+            self.counter += written;
+        }
+
+        written
+    }
+}
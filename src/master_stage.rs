@@ -0,0 +1,220 @@
+use crate::*;
+
+// The final stage of the mix before it is sent to the device. It applies the
+// master gain, then an optional look-ahead limiter, then an optional soft clip.
+// These keep loud mixes from clipping hard when many sources overlap.
+
+#[derive(Clone, Debug)]
+pub struct MasterOptions {
+    pub gain: f32,
+    pub limiter: Option<LimiterOptions>,
+    pub soft_clip: bool,
+}
+
+// The limiter delays the output by the lookahead so that it can start turning
+// the gain down before a peak arrives. No sample leaves it above the threshold.
+#[derive(Clone, Debug)]
+pub struct LimiterOptions {
+    pub threshold: f32,
+    pub lookahead: Duration,
+    pub release: Duration,
+}
+
+impl Default for MasterOptions {
+    fn default() -> Self {
+        Self { gain: 1., limiter: None, soft_clip: false }
+    }
+}
+
+impl Default for LimiterOptions {
+    fn default() -> Self {
+        Self { threshold: 0.98, lookahead: Duration::from_millis(5), release: Duration::from_millis(100) }
+    }
+}
+
+pub(crate) struct MasterStage {
+    gain: DynamicFloat,
+    limiter_options: Option<LimiterOptions>,
+    limiter: Option<Limiter>,
+    soft_clip: bool,
+}
+
+impl MasterStage {
+    pub fn new(options: &MasterOptions, gain: DynamicFloat, channels: usize, sample_rate: usize) -> Self {
+        let limiter = options.limiter.as_ref().map(|l| Limiter::new(l, channels, sample_rate));
+
+        Self { gain, limiter_options: options.limiter.clone(), limiter, soft_clip: options.soft_clip }
+    }
+
+    // The limiter's delay line depends on the channels and sample rate so it is
+    // rebuilt (and emptied) when the mixer moves to a device with a new format.
+    pub fn set_format(&mut self, channels: usize, sample_rate: usize) {
+        self.limiter = self.limiter_options.as_ref().map(|l| Limiter::new(l, channels, sample_rate));
+    }
+
+    pub fn gain(&self) -> DynamicFloat {
+        self.gain.clone()
+    }
+
+    pub fn process(&mut self, block: &mut [f32]) {
+        let gain = self.gain.get();
+        if gain != 1. { block.iter_mut().for_each(|s| *s *= gain); }
+
+        if let Some(limiter) = self.limiter.as_mut() { limiter.process(block); }
+        if self.soft_clip { block.iter_mut().for_each(|s| *s = soft_clip(*s)); }
+    }
+}
+
+// Samples below the knee are unchanged. Above it, they are smoothly squashed so
+// that they approach (but never reach) 1. The curve's slope is 1 at the knee.
+fn soft_clip(sample: f32) -> f32 {
+    const KNEE: f32 = 0.5;
+
+    let magnitude = sample.abs();
+    if magnitude <= KNEE { return sample; }
+
+    let squashed = KNEE + (1. - KNEE) * ((magnitude - KNEE) / (1. - KNEE)).tanh();
+    squashed.copysign(sample)
+}
+
+// The gain needed to keep each frame under the threshold is fed through a
+// sliding window minimum over the lookahead (plus one), then averaged over the
+// lookahead. Every value in that average is at most the gain needed by the
+// frame leaving the delay line so the output never exceeds the threshold, and
+// the averaging ramps the gain down smoothly rather than all at once.
+struct Limiter {
+    threshold: f32,
+    channels: usize,
+    lookahead: usize,
+    release_coefficient: f32,
+    delay: Vec<f32>,
+    required: Vec<f32>,
+    minimums: VecDeque<(usize, f32)>,
+    averaged: Vec<f32>,
+    sum: f32,
+    gain: f32,
+    frame_count: usize,
+}
+
+impl Limiter {
+    fn new(options: &LimiterOptions, channels: usize, sample_rate: usize) -> Self {
+        let lookahead = ((options.lookahead.as_secs_f32() * sample_rate as f32) as usize).max(1);
+        let release_frames = (options.release.as_secs_f32() * sample_rate as f32).max(1.);
+
+        Self {
+            threshold: options.threshold,
+            channels,
+            lookahead,
+            release_coefficient: 1. - (-1. / release_frames).exp(),
+            delay: vec![0.; lookahead * channels],
+            required: vec![1.; lookahead],
+            minimums: VecDeque::with_capacity(lookahead + 2),
+            averaged: vec![1.; lookahead],
+            sum: lookahead as f32,
+            gain: 1.,
+            frame_count: 0,
+        }
+    }
+
+    fn process(&mut self, block: &mut [f32]) {
+        for frame in block.chunks_mut(self.channels) {
+            let slot = self.frame_count % self.lookahead;
+
+            let peak = frame.iter().fold(0., |max: f32, s| max.max(s.abs()));
+            let required = if peak > self.threshold { self.threshold / peak } else { 1. };
+
+            while self.minimums.back().is_some_and(|(_, g)| *g >= required) { self.minimums.pop_back(); }
+            self.minimums.push_back((self.frame_count, required));
+
+            while self.minimums.front().is_some_and(|(i, _)| i + self.lookahead < self.frame_count) { self.minimums.pop_front(); }
+            let minimum = self.minimums.front().unwrap().1;
+
+            self.sum += minimum - self.averaged[slot];
+            self.averaged[slot] = minimum;
+
+            // The frame leaving the delay line is the oldest one in the window.
+            let delayed_required = self.required[slot];
+            self.required[slot] = required;
+
+            let target = (self.sum / self.lookahead as f32).min(delayed_required);
+
+            if target < self.gain {
+                self.gain = target;
+            } else {
+                self.gain += (target - self.gain) * self.release_coefficient;
+            }
+
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                let index = slot * self.channels + channel;
+                let delayed = std::mem::replace(&mut self.delay[index], *sample);
+
+                *sample = delayed * self.gain;
+            }
+
+            self.frame_count += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn limiter(threshold: f32, lookahead_frames: u64) -> MasterStage {
+        let limiter = LimiterOptions { threshold, lookahead: Duration::from_secs(lookahead_frames), ..Default::default() };
+        let options = MasterOptions { limiter: Some(limiter), ..Default::default() };
+
+        MasterStage::new(&options, DynamicFloat::new(1.), 1, 1)
+    }
+
+    #[test]
+    fn it_applies_the_master_gain() {
+        let gain = DynamicFloat::new(0.5);
+        let mut master = MasterStage::new(&MasterOptions::default(), gain.clone(), 1, 44100);
+
+        let mut block = [1., 2., 3.];
+        master.process(&mut block);
+        assert_eq!(block, [0.5, 1., 1.5]);
+
+        gain.set(2.);
+        master.process(&mut block);
+        assert_eq!(block, [1., 2., 3.]);
+    }
+
+    #[test]
+    fn it_delays_the_signal_by_the_lookahead_when_limiting() {
+        let mut master = limiter(1., 3);
+
+        let mut block = [0.1, 0.2, 0.3, 0.4, 0.5];
+        master.process(&mut block);
+        assert_eq!(block, [0., 0., 0., 0.1, 0.2]);
+    }
+
+    #[test]
+    fn it_never_lets_samples_exceed_the_threshold_when_limiting() {
+        let mut master = limiter(0.5, 4);
+
+        let mut block = [0.2, 0.2, 0.2, 2., -3., 0.2, 0.2, 0.2, 0.2, 0.2, 0.2, 0.2];
+        master.process(&mut block);
+
+        assert!(block.iter().all(|s| s.abs() <= 0.5 + 1e-6));
+        assert!((block[8] + 0.5).abs() < 1e-6);
+
+        // The gain is ramped down before the peak rather than all at once.
+        assert!(block[4] < 0.2 && block[4] > block[5]);
+    }
+
+    #[test]
+    fn it_soft_clips_samples_so_they_stay_below_one() {
+        let options = MasterOptions { soft_clip: true, ..Default::default() };
+        let mut master = MasterStage::new(&options, DynamicFloat::new(1.), 1, 44100);
+
+        let mut block = [0.25, -0.5, 0.9, -2., 100.];
+        master.process(&mut block);
+
+        assert_eq!(block[..2], [0.25, -0.5]);
+        assert!(block[2] > 0.8 && block[2] < 0.9);
+        assert!(block[3] < -0.9 && block[3] > -1.);
+        assert!(block[4] <= 1.);
+    }
+}
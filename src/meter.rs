@@ -0,0 +1,264 @@
+use crate::*;
+
+// Measures the level of each channel of a source as it plays. The peak and RMS
+// are published every WINDOW so that they can be read from another thread, e.g.
+// to draw VU meters in a UI or to check for silent or clipping sources.
+//
+// The peak hold is the largest peak over the last PEAK_HOLD. When the source
+// finishes, the peak and RMS drop to zero but the peak hold is left as it was.
+
+pub struct Meter<S: Iterator<Item=f32>> {
+    state: MeterState,
+    source: S,
+}
+
+const WINDOW: Duration = Duration::from_millis(50);
+const PEAK_HOLD: Duration = Duration::from_secs(1);
+
+// The mixer's output meter has room for this many channels so that its levels
+// never need to be reallocated when the mixer moves device. Any more channels
+// than this aren't metered.
+const MAX_OUTPUT_CHANNELS: usize = 32;
+
+// A handle to the levels that a meter publishes. It is cheap to clone and
+// reading it never locks. The mixer's output meter changes its number of
+// channels if the mixer moves to a device with a different format, so the count
+// is atomic and the levels have room for the most channels it can meter.
+// Channels that are out of range read as 0.
+#[derive(Clone, Debug)]
+pub struct MeterLevels {
+    channels: Arc<[ChannelLevels]>,
+    count: Arc<AtomicUsize>,
+    clipped: Arc<AtomicBool>,
+}
+
+#[derive(Debug, Default)]
+struct ChannelLevels {
+    peak: AtomicF32,
+    rms: AtomicF32,
+    peak_hold: AtomicF32,
+}
+
+// The audio thread's side of a meter. It is also used for the mixer's output.
+pub(crate) struct MeterState {
+    levels: MeterLevels,
+    window_frames: usize,
+    hold_frames: usize,
+    peaks: Vec<f32>,
+    squares: Vec<f32>,
+    held: Vec<(f32, usize)>,
+    channel: usize,
+    frames: usize,
+}
+
+impl<S: Iterator<Item=f32>> Meter<S> {
+    pub fn new(channels: usize, sample_rate: usize, source: S) -> Self {
+        Self { state: MeterState::new(channels, sample_rate), source }
+    }
+
+    pub fn levels(&self) -> MeterLevels {
+        self.state.levels()
+    }
+}
+
+impl<S: Iterator<Item=f32>> Iterator for Meter<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = self.source.next();
+
+        match sample {
+            Some(s) => self.state.process(s),
+            None => self.state.finish(),
+        }
+
+        sample
+    }
+}
+
+impl<S: Iterator<Item=f32> + BlockSource> BlockSource for Meter<S> {
+    fn fill_block(&mut self, block: &mut [f32]) -> usize {
+        let written = self.source.fill_block(block);
+
+        self.state.process_block(&block[..written]);
+        if written < block.len() { self.state.finish(); }
+
+        written
+    }
+}
+
+impl MeterLevels {
+    fn new(channels: usize, capacity: usize) -> Self {
+        let levels = (0..capacity.max(channels)).map(|_| ChannelLevels::default()).collect();
+
+        Self { channels: levels, count: Arc::new(AtomicUsize::new(channels)), clipped: Arc::default() }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn peak(&self, channel: usize) -> f32 {
+        self.channel(channel).map_or(0., |l| l.peak.load(Ordering::Relaxed))
+    }
+
+    pub fn rms(&self, channel: usize) -> f32 {
+        self.channel(channel).map_or(0., |l| l.rms.load(Ordering::Relaxed))
+    }
+
+    pub fn peak_hold(&self, channel: usize) -> f32 {
+        self.channel(channel).map_or(0., |l| l.peak_hold.load(Ordering::Relaxed))
+    }
+
+    fn channel(&self, channel: usize) -> Option<&ChannelLevels> {
+        self.metered().get(channel)
+    }
+
+    // The levels that are being metered, which is all of them except for an
+    // output with more than MAX_OUTPUT_CHANNELS.
+    fn metered(&self) -> &[ChannelLevels] {
+        &self.channels[..self.channels()]
+    }
+
+    // Whether any sample has reached 1 or more since the meter was created or
+    // since reset_clipped was last called.
+    pub fn has_clipped(&self) -> bool {
+        self.clipped.load(Ordering::Relaxed)
+    }
+
+    pub fn reset_clipped(&self) {
+        self.clipped.store(false, Ordering::Relaxed);
+    }
+}
+
+impl MeterState {
+    pub fn new(channels: usize, sample_rate: usize) -> Self {
+        Self::with_levels(MeterLevels::new(channels, channels), channels, sample_rate)
+    }
+
+    pub fn for_output(channels: usize, sample_rate: usize) -> Self {
+        Self::with_levels(MeterLevels::new(channels, MAX_OUTPUT_CHANNELS), channels, sample_rate)
+    }
+
+    fn with_levels(levels: MeterLevels, channels: usize, sample_rate: usize) -> Self {
+        let frames_for = |duration: Duration| ((duration.as_secs_f64() * sample_rate as f64) as usize).max(1);
+
+        Self {
+            levels,
+            window_frames: frames_for(WINDOW),
+            hold_frames: frames_for(PEAK_HOLD),
+            peaks: vec![0.; channels],
+            squares: vec![0.; channels],
+            held: vec![(0., 0); channels],
+            channel: 0,
+            frames: 0,
+        }
+    }
+
+    pub fn levels(&self) -> MeterLevels {
+        self.levels.clone()
+    }
+
+    // Starts metering a new format while keeping the same levels, so that
+    // MeterLevels taken before the mixer moved device carry on working.
+    pub fn set_format(&mut self, channels: usize, sample_rate: usize) {
+        let levels = self.levels.clone();
+
+        for level in levels.channels.iter() {
+            level.peak.store(0., Ordering::Relaxed);
+            level.rms.store(0., Ordering::Relaxed);
+            level.peak_hold.store(0., Ordering::Relaxed);
+        }
+
+        levels.count.store(channels.min(levels.channels.len()), Ordering::Relaxed);
+        *self = Self::with_levels(levels, channels, sample_rate);
+    }
+
+    pub fn process_block(&mut self, block: &[f32]) {
+        block.iter().for_each(|s| self.process(*s));
+    }
+
+    pub fn process(&mut self, sample: f32) {
+        let channel = self.channel;
+
+        self.peaks[channel] = self.peaks[channel].max(sample.abs());
+        self.squares[channel] += sample * sample;
+
+        self.channel += 1;
+        if self.channel < self.peaks.len() { return; }
+
+        self.channel = 0;
+        self.frames += 1;
+
+        if self.frames == self.window_frames { self.publish(); }
+    }
+
+    fn publish(&mut self) {
+        for (channel, levels) in self.levels.metered().iter().enumerate() {
+            let peak = std::mem::take(&mut self.peaks[channel]);
+            let rms = (std::mem::take(&mut self.squares[channel]) / self.frames as f32).sqrt();
+
+            let (held, age) = &mut self.held[channel];
+            *age += self.frames;
+
+            if peak >= *held || *age >= self.hold_frames { (*held, *age) = (peak, 0); }
+            if peak >= 1. { self.levels.clipped.store(true, Ordering::Relaxed); }
+
+            levels.peak.store(peak, Ordering::Relaxed);
+            levels.rms.store(rms, Ordering::Relaxed);
+            levels.peak_hold.store(*held, Ordering::Relaxed);
+        }
+
+        self.frames = 0;
+    }
+
+    fn finish(&mut self) {
+        if self.frames > 0 { self.publish(); }
+
+        for levels in self.levels.metered() {
+            levels.peak.store(0., Ordering::Relaxed);
+            levels.rms.store(0., Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_publishes_the_peak_and_rms_of_each_channel_per_window() {
+        // The window is 5 frames at this sample rate.
+        let source = [0.5, 1., -0.5, -1., 0.5, 1., -0.5, -1., 0.5, 0.].into_iter().chain(std::iter::repeat(0.));
+        let mut meter = Meter::new(2, 100, source);
+        let levels = meter.levels();
+
+        meter.by_ref().take(9).for_each(drop);
+        assert_eq!(levels.peak(0), 0.);
+
+        meter.next();
+        assert_eq!((levels.peak(0), levels.rms(0)), (0.5, 0.5));
+        assert_eq!(levels.peak(1), 1.);
+        assert!((levels.rms(1) - 0.8f32.sqrt()).abs() < 1e-6);
+        assert!(levels.has_clipped());
+    }
+
+    #[test]
+    fn it_holds_the_largest_peak_until_it_expires() {
+        let loud = std::iter::repeat_n(0.8, 5);
+        let quiet = std::iter::repeat_n(0.2, 100);
+
+        let mut meter = Meter::new(1, 100, loud.chain(quiet));
+        let levels = meter.levels();
+
+        meter.by_ref().take(10).for_each(drop);
+        assert_eq!((levels.peak(0), levels.peak_hold(0)), (0.2, 0.8));
+
+        meter.by_ref().take(95).for_each(drop);
+        assert_eq!(levels.peak_hold(0), 0.2);
+        assert!(!levels.has_clipped());
+
+        meter.next();
+        assert_eq!(levels.peak(0), 0.);
+    }
+}
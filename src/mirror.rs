@@ -0,0 +1,345 @@
+use crate::*;
+use crate::mixer_error::ErrorHandler;
+use crate::ring_buffer::RingBuffer;
+use std::sync::OnceLock;
+use std::thread::{self, Thread};
+
+// Plays the mix on another output device at the same time as the mixer's own
+// device, e.g. on headphones and on a virtual loopback device for streaming.
+// A bus can be mirrored instead of the whole mix, e.g. to leave out voice chat.
+//
+// The mixer writes into a ring buffer that the other device's stream reads from
+// and converts to its own sample rate and channels. The two devices' clocks
+// won't run at exactly the same speed so the reader speeds up or slows down very
+// slightly to keep the buffer half full. Drop the Mirror to stop mirroring.
+
+pub struct Mirror {
+    channels: usize,
+    sample_rate: usize,
+    stream_thread: Option<(Thread, Arc<AtomicBool>)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MirrorOptions {
+    // The bus to mirror. The whole mix is mirrored if None.
+    pub bus: Option<Bus>,
+
+    // A longer buffer is less likely to run dry if the mixer's device asks for
+    // large blocks but the mirror is delayed by half of it.
+    pub buffer_duration: Duration,
+}
+
+// The mixer's side of a mirror. A tap's format never changes. If the mixer
+// moves to a device with a different one it writes to a new tap, sized for the
+// new format, that is linked from the old one. The reader moves onto it at the
+// start of a frame so it never reads samples in a layout it doesn't expect.
+pub(crate) struct MirrorTap {
+    pub bus: Option<usize>,
+    pub buffer: RingBuffer,
+    channels: usize,
+    sample_rate: usize,
+    duration: Duration,
+    next: OnceLock<Arc<MirrorTap>>,
+}
+
+// The reader holds on to the first tap because the mixer stops writing to the
+// mirror once nothing else does.
+struct MirrorReader {
+    _first: Arc<MirrorTap>,
+    tap: Arc<MirrorTap>,
+    channels: usize,
+    step: f64,
+    position: f64,
+    before: Vec<f32>,
+    after: Vec<f32>,
+    frame: Vec<f32>,
+    channel: usize,
+    fill: f64,
+    primed: bool,
+}
+
+// The most the reader changes speed by to compensate for drift, i.e. 0.2%.
+const MAX_CORRECTION: f64 = 0.002;
+
+// How quickly the measured fill level follows the buffer, per frame. The mixer
+// writes in blocks so the level is smoothed over a few thousand frames.
+const SMOOTHING: f64 = 0.0005;
+
+// The reader reserves room for this many channels up front so that it doesn't
+// allocate on the audio thread when the mixer's format changes.
+const MAX_CHANNELS: usize = 32;
+
+impl Mirror {
+    pub(crate) fn for_device(device: Device, tap: Arc<MirrorTap>, errors: Arc<ErrorHandler>) -> Result<Self, MixerError> {
+        let (sender, receiver) = channel();
+        let closed = Arc::new(AtomicBool::new(false));
+        let closed_clone = closed.clone();
+
+        // Streams can't be sent between threads so this one is built and kept
+        // on a thread of its own, the same as for AudioInput.
+        let handle = thread::spawn(move || match Self::build_stream(&device, tap, errors) {
+            Ok((stream, channels, sample_rate)) => {
+                let _ = sender.send(Ok((channels, sample_rate)));
+
+                while !closed_clone.load(Ordering::Relaxed) { thread::park(); }
+                drop(stream);
+            },
+            Err(error) => { let _ = sender.send(Err(error)); },
+        });
+
+        let (channels, sample_rate) = receiver.recv().map_err(|_| MixerError::StreamThreadPanicked)??;
+        let stream_thread = Some((handle.thread().clone(), closed));
+
+        Ok(Self { channels, sample_rate, stream_thread })
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> usize {
+        self.sample_rate
+    }
+
+    fn build_stream(device: &Device, tap: Arc<MirrorTap>, errors: Arc<ErrorHandler>) -> Result<(Stream, usize, usize), MixerError> {
+        let config = device.default_output_config()?;
+
+        let channels = config.channels() as usize;
+        let sample_rate = config.sample_rate().0 as usize;
+
+        let stream = match config.sample_format() {
+            SampleFormat::F32 => Self::build_output_stream::<f32>(device, &config.into(), tap, errors),
+            SampleFormat::I16 => Self::build_output_stream::<i16>(device, &config.into(), tap, errors),
+            SampleFormat::U16 => Self::build_output_stream::<u16>(device, &config.into(), tap, errors),
+        }?;
+
+        stream.play()?;
+        Ok((stream, channels, sample_rate))
+    }
+
+    fn build_output_stream<S: Sample>(device: &Device, config: &StreamConfig, tap: Arc<MirrorTap>, errors: Arc<ErrorHandler>) -> Result<Stream, MixerError> {
+        let (channels, sample_rate) = (config.channels as usize, config.sample_rate.0 as usize);
+
+        let reader = MirrorReader::new(tap, sample_rate);
+        let mut converter = IntoChannels::new(reader.channels, channels, reader);
+
+        // The reader is checked for a new tap once per frame so that the mix
+        // is read in the new format from the first frame that it's written in.
+        let stream = device.build_output_stream::<S, _, _>(config, move |out, _info| {
+            for frame in out.chunks_mut(channels) {
+                let reader = converter.source_mut();
+
+                if reader.follow(sample_rate) {
+                    let from = reader.channels;
+                    converter.set_from(from);
+                }
+
+                frame.iter_mut().zip(&mut converter).for_each(|(o, s)| *o = Sample::from(&s));
+            }
+        }, move |error| {
+            errors.report(MixerError::Stream(error));
+        })?;
+
+        Ok(stream)
+    }
+}
+
+impl Default for MirrorOptions {
+    fn default() -> Self {
+        Self { bus: None, buffer_duration: Duration::from_millis(100) }
+    }
+}
+
+impl Drop for Mirror {
+    fn drop(&mut self) {
+        if let Some((thread, closed)) = self.stream_thread.take() {
+            closed.store(true, Ordering::Relaxed);
+            thread.unpark();
+        }
+    }
+}
+
+impl MirrorTap {
+    pub fn new(bus: Option<usize>, channels: usize, sample_rate: usize, duration: Duration) -> Self {
+        let frames = (duration.as_secs_f64() * sample_rate as f64).ceil() as usize;
+        let buffer = RingBuffer::new(frames.max(2) * channels);
+
+        Self { bus, buffer, channels, sample_rate, duration, next: OnceLock::new() }
+    }
+
+    // The tap that the mixer currently writes to.
+    pub fn latest(&self) -> &MirrorTap {
+        let mut tap = self;
+        while let Some(next) = tap.next.get() { tap = next; }
+        tap
+    }
+
+    // Called when the mixer's format changes, which isn't on the audio thread,
+    // so that the new buffer is allocated there. The old taps are kept until the
+    // mirror is dropped because the reader might still be holding one of them.
+    pub fn set_format(&self, channels: usize, sample_rate: usize) {
+        let latest = self.latest();
+        let _ = latest.next.set(Arc::new(Self::new(latest.bus, channels, sample_rate, latest.duration)));
+    }
+}
+
+impl MirrorReader {
+    fn new(tap: Arc<MirrorTap>, to_rate: usize) -> Self {
+        let buffer = || Vec::with_capacity(MAX_CHANNELS);
+        let mut reader = Self { _first: tap.clone(), tap, channels: 0, step: 0., position: 0., before: buffer(), after: buffer(), frame: buffer(), channel: 0, fill: 0., primed: false };
+
+        reader.follow(to_rate);
+        reader.reset(to_rate);
+        reader
+    }
+
+    // Moves onto the newest tap if the mixer's format has changed. Whatever was
+    // left in the old tap's buffer is skipped. Returns whether it moved.
+    fn follow(&mut self, to_rate: usize) -> bool {
+        let mut moved = false;
+
+        while let Some(next) = self.tap.next.get().cloned() {
+            (self.tap, moved) = (next, true);
+        }
+
+        if moved { self.reset(to_rate); }
+        moved
+    }
+
+    // Starts reading the tap's format, waiting for the buffer to fill again.
+    fn reset(&mut self, to_rate: usize) {
+        let (channels, from_rate) = (self.tap.channels, self.tap.sample_rate);
+
+        (self.channels, self.step, self.position, self.channel, self.primed) = (channels, from_rate as f64 / to_rate as f64, 0., 0, false);
+        self.fill = self.tap.buffer.len() as f64 / channels as f64;
+
+        for buffer in [&mut self.before, &mut self.after, &mut self.frame] {
+            buffer.clear();
+            buffer.resize(channels, 0.);
+        }
+    }
+
+    // Waits for the buffer to fill to the target before reading so that it has
+    // room to drift either way. It waits again if the buffer ever runs dry.
+    fn next_frame(&mut self) {
+        let target = (self.tap.buffer.capacity() / self.channels / 2) as f64;
+        let len = self.tap.buffer.len() / self.channels;
+
+        self.fill += (len as f64 - self.fill) * SMOOTHING;
+
+        if !self.primed {
+            if (len as f64) < target { return self.frame.fill(0.); }
+            (self.primed, self.fill) = (true, len as f64);
+        }
+
+        // Read faster when the buffer is filling up and slower when it's draining.
+        let correction = ((self.fill - target) / target).clamp(-1., 1.) * MAX_CORRECTION;
+        self.position += self.step * (1. + correction);
+
+        while self.position >= 1. {
+            if self.tap.buffer.len() < self.channels {
+                self.primed = false;
+                return self.frame.fill(0.);
+            }
+
+            swap(&mut self.before, &mut self.after);
+            self.after.iter_mut().for_each(|a| *a = self.tap.buffer.pop().unwrap_or(0.));
+            self.position -= 1.;
+        }
+
+        let t = self.position as f32;
+
+        for ((f, b), a) in self.frame.iter_mut().zip(&self.before).zip(&self.after) {
+            *f = b + t * (a - b);
+        }
+    }
+}
+
+impl Iterator for MirrorReader {
+    type Item = f32;
+
+    // The reader never finishes. It returns silence if the mixer stops writing.
+    fn next(&mut self) -> Option<f32> {
+        if self.channel == 0 { self.next_frame(); }
+
+        let sample = self.frame[self.channel];
+        self.channel = (self.channel + 1) % self.channels;
+
+        Some(sample)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // The reader's speed is corrected slightly so round away the difference.
+    fn rounded<I: Iterator<Item=f32>>(samples: I) -> Vec<f32> {
+        samples.map(|s| (s * 100.).round() / 100.).collect()
+    }
+
+    #[test]
+    fn it_returns_silence_until_the_buffer_is_half_full() {
+        let tap = Arc::new(MirrorTap::new(None, 1, 10, Duration::from_secs(1)));
+        let mut reader = MirrorReader::new(tap.clone(), 10);
+
+        tap.buffer.push_frames([1.; 4].into_iter(), 1);
+        assert_eq!(reader.next(), Some(0.));
+
+        tap.buffer.push_frames([1.; 4].into_iter(), 1);
+        assert_eq!(rounded(reader.by_ref().take(3)), vec![0., 1., 1.]);
+    }
+
+    #[test]
+    fn it_converts_the_sample_rate_of_the_mix() {
+        let tap = Arc::new(MirrorTap::new(None, 2, 4, Duration::from_secs(1)));
+        let mut reader = MirrorReader::new(tap.clone(), 8);
+
+        tap.buffer.push_frames([1., 2., 3., 4.].into_iter(), 2);
+
+        // The first frame is interpolated from silence, like IntoSampleRate.
+        assert_eq!(rounded(reader.by_ref().take(10)), vec![0., 0., 0., 0., 0.5, 1., 1., 2., 2., 3.]);
+    }
+
+    #[test]
+    fn it_reads_faster_when_the_buffer_is_fuller_than_the_target() {
+        let consumed = |frames: usize| {
+            let tap = Arc::new(MirrorTap::new(None, 1, 1000, Duration::from_secs(10)));
+            let mut reader = MirrorReader::new(tap.clone(), 1000);
+
+            tap.buffer.push_frames(std::iter::repeat_n(0.5, frames), 1);
+            reader.by_ref().take(5000).for_each(drop);
+
+            frames - tap.buffer.len()
+        };
+
+        assert!(consumed(10000) > 5000);
+    }
+
+    #[test]
+    fn it_sizes_the_buffer_for_the_new_format_when_the_format_changes() {
+        let tap = MirrorTap::new(None, 1, 10, Duration::from_secs(1));
+        tap.set_format(4, 20);
+
+        assert_eq!(tap.buffer.capacity(), 10);
+        assert_eq!(tap.latest().buffer.capacity(), 80);
+        assert_eq!(tap.latest().duration, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn it_skips_the_rest_of_the_old_format_when_the_format_changes() {
+        let tap = Arc::new(MirrorTap::new(None, 1, 10, Duration::from_secs(1)));
+        let mut reader = MirrorReader::new(tap.clone(), 10);
+
+        tap.buffer.push_frames([1.; 6].into_iter(), 1);
+        assert_eq!(rounded(reader.by_ref().take(2)), vec![0., 1.]);
+
+        tap.set_format(2, 10);
+        tap.latest().buffer.push_frames([2., 3.].repeat(6).into_iter(), 2);
+
+        assert!(reader.follow(10));
+        assert_eq!(reader.channels, 2);
+        assert_eq!(rounded(reader.by_ref().take(4)), vec![0., 0., 2., 3.]);
+        assert_eq!(tap.buffer.len(), 4);
+    }
+}
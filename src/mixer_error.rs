@@ -0,0 +1,170 @@
+use crate::*;
+use crate::device_recovery::RecoveryCommand;
+use cpal::{BuildStreamError, PlayStreamError, StreamError, SupportedStreamConfigsError};
+use std::sync::OnceLock;
+
+// The errors that can occur when building an AudioMixer for a device or while
+// it is playing. The Unsupported variants list what the device does support so
+// that a caller can pick something else, e.g. by showing them in a menu.
+
+#[derive(Debug)]
+pub enum MixerError {
+    DeviceNotAvailable,
+    StreamThreadPanicked,
+    DefaultConfig(DefaultStreamConfigError),
+    SupportedConfigs(SupportedStreamConfigsError),
+    UnsupportedChannels { requested: usize, supported: Vec<usize> },
+    UnsupportedSampleFormat { requested: SampleFormat, supported: Vec<SampleFormat> },
+    UnsupportedSampleRate { requested: usize, supported: Vec<(usize, usize)> },
+    UnsupportedBufferSize { requested: usize, supported: Vec<(usize, usize)> },
+    BuildStream(BuildStreamError),
+    PlayStream(PlayStreamError),
+    Stream(StreamError),
+    ForeignBus { name: String },
+    SourcePanicked { id: usize, message: String },
+    BusPanicked { index: usize, message: String },
+    RecorderPanicked { message: String },
+    MixPanicked { message: String },
+}
+
+type ErrorFunction = Box<dyn FnMut(MixerError) + Send>;
+
+// Errors that happen while the mixer is playing are passed to this. They come
+// from the stream's error callback and the recovery thread. Some hosts call the
+// error callback on the stream's audio thread, e.g. ALSA calls it from the
+// thread that runs the data callback, so the error function can run there too.
+// Stream errors are rare and mean the stream is already failing so it is fine
+// for this to lock. Panics caught in the data callback use
+// report_from_audio_thread instead.
+pub(crate) struct ErrorHandler {
+    function: Arc<Mutex<Option<ErrorFunction>>>,
+    recovery: Mutex<Option<Sender<RecoveryCommand>>>,
+}
+
+type AudioThreadError = (Arc<Mutex<Option<ErrorFunction>>>, MixerError);
+
+// How many errors from audio threads can wait to be reported before more are
+// dropped. The channel's buffer is allocated up front.
+const MAX_AUDIO_THREAD_ERRORS: usize = 64;
+
+impl std::fmt::Display for MixerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::DeviceNotAvailable => write!(f, "the output device is not available"),
+            Self::StreamThreadPanicked => write!(f, "the thread that builds the stream panicked"),
+            Self::DefaultConfig(error) => write!(f, "{}", error),
+            Self::SupportedConfigs(error) => write!(f, "{}", error),
+            Self::UnsupportedChannels { requested, supported } =>
+                write!(f, "{} channels are not supported by the device (supported: {:?})", requested, supported),
+            Self::UnsupportedSampleFormat { requested, supported } =>
+                write!(f, "the {:?} sample format is not supported by the device (supported: {:?})", requested, supported),
+            Self::UnsupportedSampleRate { requested, supported } =>
+                write!(f, "a sample rate of {} is not supported by the device (supported ranges: {:?})", requested, supported),
+            Self::UnsupportedBufferSize { requested, supported } =>
+                write!(f, "a buffer size of {} frames is not supported by the device (supported ranges: {:?})", requested, supported),
+            Self::BuildStream(error) => write!(f, "{}", error),
+            Self::PlayStream(error) => write!(f, "{}", error),
+            Self::Stream(error) => write!(f, "{}", error),
+            Self::ForeignBus { name } => write!(f, "the {} bus was created by another AudioMixer", name),
+            Self::SourcePanicked { id, message } => write!(f, "source {} panicked and was removed: {}", id, message),
+            Self::BusPanicked { index, message } => write!(f, "the effects on bus {} panicked and were bypassed: {}", index, message),
+            Self::RecorderPanicked { message } => write!(f, "the recorder panicked and was stopped: {}", message),
+            Self::MixPanicked { message } => write!(f, "the mix panicked and a buffer was silenced: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for MixerError {}
+
+// The reporting thread is started by the first handler so that an audio
+// thread never has to start it.
+impl Default for ErrorHandler {
+    fn default() -> Self {
+        audio_thread_reporter();
+        Self { function: Arc::default(), recovery: Mutex::default() }
+    }
+}
+
+// Errors from every mixer's audio thread are reported on one shared thread so
+// that the callbacks only have to push them onto a channel. It runs for as long
+// as the process does.
+fn audio_thread_reporter() -> &'static SyncSender<AudioThreadError> {
+    static REPORTER: OnceLock<SyncSender<AudioThreadError>> = OnceLock::new();
+
+    REPORTER.get_or_init(|| {
+        let (sender, receiver) = sync_channel::<AudioThreadError>(MAX_AUDIO_THREAD_ERRORS);
+        std::thread::spawn(move || receiver.iter().for_each(|(function, error)| report(&function, error)));
+
+        sender
+    })
+}
+
+impl ErrorHandler {
+    pub fn set_function(&self, function: Option<ErrorFunction>) {
+        *self.function.lock().unwrap() = function;
+    }
+
+    pub fn set_recovery(&self, recovery: Sender<RecoveryCommand>) {
+        *self.recovery.lock().unwrap() = Some(recovery);
+    }
+
+    pub fn report(&self, error: MixerError) {
+        report(&self.function, error);
+    }
+
+    // Hands the error to the reporting thread so that the audio callback never
+    // waits on the error function. The error is dropped if the channel is full.
+    pub fn report_from_audio_thread(&self, error: MixerError) {
+        let _ = audio_thread_reporter().try_send((self.function.clone(), error));
+    }
+
+    // The generation identifies the stream that the error came from.
+    pub fn stream_error(&self, error: StreamError, generation: usize) {
+        if let (StreamError::DeviceNotAvailable, Some(recovery)) = (&error, self.recovery.lock().unwrap().as_ref()) {
+            let _ = recovery.send(RecoveryCommand::DeviceLost(generation));
+        }
+
+        self.report(MixerError::Stream(error));
+    }
+}
+
+fn report(function: &Mutex<Option<ErrorFunction>>, error: MixerError) {
+    match function.lock().unwrap().as_mut() {
+        Some(function) => function(error),
+        None => eprintln!("audio mixer error: {}", error),
+    }
+}
+
+impl MixerError {
+    // Panics usually have a &str or String payload, e.g. from panic!("{}", x).
+    pub(crate) fn panic_message(payload: Box<dyn Any + Send>) -> String {
+        match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => payload.downcast_ref::<&str>().map_or("unknown panic", |m| m).to_string(),
+        }
+    }
+}
+
+impl From<DefaultStreamConfigError> for MixerError {
+    fn from(error: DefaultStreamConfigError) -> Self {
+        Self::DefaultConfig(error)
+    }
+}
+
+impl From<SupportedStreamConfigsError> for MixerError {
+    fn from(error: SupportedStreamConfigsError) -> Self {
+        Self::SupportedConfigs(error)
+    }
+}
+
+impl From<BuildStreamError> for MixerError {
+    fn from(error: BuildStreamError) -> Self {
+        Self::BuildStream(error)
+    }
+}
+
+impl From<PlayStreamError> for MixerError {
+    fn from(error: PlayStreamError) -> Self {
+        Self::PlayStream(error)
+    }
+}
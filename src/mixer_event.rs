@@ -0,0 +1,63 @@
+use crate::*;
+
+// Events are sent from the audio thread over channels returned by
+// AudioMixer::events so they can be received on any other thread. The frame is
+// the output frame at which the event happened, as per AudioMixer::position.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MixerEvent {
+    // The source produced its first sample.
+    Started { id: usize, frame: usize },
+
+    // The source ran out of samples.
+    Finished { id: usize, frame: usize },
+
+    // The source was stopped or removed via its SourceHandle before it finished.
+    Removed { id: usize, frame: usize },
+
+    // The source was faded out (or didn't start) to stay within the VoiceLimit.
+    Stolen { id: usize, frame: usize },
+}
+
+impl MixerEvent {
+    pub fn id(&self) -> usize {
+        match self { Self::Started { id, .. } | Self::Finished { id, .. } | Self::Removed { id, .. } | Self::Stolen { id, .. } => *id }
+    }
+
+    pub fn frame(&self) -> usize {
+        match self { Self::Started { frame, .. } | Self::Finished { frame, .. } | Self::Removed { frame, .. } | Self::Stolen { frame, .. } => *frame }
+    }
+}
+
+// How many events a channel holds before more are dropped. The channels are
+// bounded so that sending from the audio thread never allocates.
+const MAX_PENDING_EVENTS: usize = 1024;
+
+pub(crate) fn event_channel() -> (SyncSender<MixerEvent>, Receiver<MixerEvent>) {
+    sync_channel(MAX_PENDING_EVENTS)
+}
+
+pub(crate) struct EventSenders {
+    senders: Vec<SyncSender<MixerEvent>>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl EventSenders {
+    pub fn new(dropped: Arc<AtomicUsize>) -> Self {
+        Self { senders: vec![], dropped }
+    }
+
+    pub fn subscribe(&mut self, sender: SyncSender<MixerEvent>) {
+        self.senders.push(sender);
+    }
+
+    // Channels whose receiver has been dropped are unsubscribed. Events are
+    // dropped and counted for channels that are full.
+    pub fn send(&mut self, event: MixerEvent) {
+        self.senders.retain(|s| match s.try_send(event) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => { self.dropped.fetch_add(1, Ordering::Relaxed); true },
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+}
@@ -0,0 +1,13 @@
+use crate::*;
+
+// Options that are fixed when the AudioMixer is constructed, e.g. with
+// AudioMixer::for_default_device_with_options. Use MixerOptions::default()
+// for the behavior of AudioMixer::for_default_device.
+
+#[derive(Clone, Debug, Default)]
+pub struct MixerOptions {
+    pub master: MasterOptions,
+    pub recovery: RecoveryPolicy,
+    pub voice_limit: Option<VoiceLimit>,
+    pub dither: Option<DitherOptions>,
+}
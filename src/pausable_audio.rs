@@ -45,3 +45,9 @@ impl<P: MaybeDynamic<bool>, S: Iterator<Item=f32>> Iterator for PausableAudio<P,
         (self.strategy)(self)
     }
 }
+
+impl<P: MaybeDynamic<bool>, S: Iterator<Item=f32>> BlockSource for PausableAudio<P, S> {
+    fn fill_block(&mut self, block: &mut [f32]) -> usize {
+        fill_from_iterator(self, block)
+    }
+}
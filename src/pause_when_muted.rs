@@ -41,3 +41,9 @@ impl<V: MaybeDynamic<f32>, S: Iterator<Item=f32>> Iterator for PauseWhenMuted<V,
         }
     }
 }
+
+impl<V: MaybeDynamic<f32>, S: Iterator<Item=f32>> BlockSource for PauseWhenMuted<V, S> {
+    fn fill_block(&mut self, block: &mut [f32]) -> usize {
+        fill_from_iterator(self, block)
+    }
+}
@@ -0,0 +1,105 @@
+use crate::*;
+use std::sync::atomic::{AtomicI64, AtomicU64};
+
+// Maps the mixer's frames to the instants at which they reach the speaker. On
+// each callback, the audio thread works out when the first frame of the buffer
+// will be played from the callback's timestamps. That gives the instant that
+// frame zero would have been played at and the rest follow from the sample rate.
+//
+// The mapping is stored as nanoseconds from an epoch so that it fits in an
+// atomic. It jitters a little with the scheduling of the audio thread.
+
+pub(crate) struct PlaybackClock {
+    epoch: Instant,
+    origin: AtomicI64,
+    sample_rate: AtomicUsize,
+    output_latency: AtomicU64,
+}
+
+const UNKNOWN: i64 = i64::MIN;
+
+impl Default for PlaybackClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PlaybackClock {
+    pub fn new() -> Self {
+        Self { epoch: Instant::now(), origin: AtomicI64::new(UNKNOWN), sample_rate: AtomicUsize::new(0), output_latency: AtomicU64::new(0) }
+    }
+
+    // Called on the audio thread at the start of each callback.
+    pub fn update(&self, first_frame: usize, sample_rate: usize, output_latency: Duration, now: Instant) {
+        let played_at = nanos_between(self.epoch, now) + output_latency.as_nanos() as i64;
+        let origin = played_at - (first_frame as f64 * 1e9 / sample_rate as f64) as i64;
+
+        self.sample_rate.store(sample_rate, Ordering::Relaxed);
+        self.output_latency.store(output_latency.as_nanos() as u64, Ordering::Relaxed);
+        self.origin.store(origin, Ordering::Release);
+    }
+
+    // Called when the sample rate changes or the mixer stops playing on a device.
+    pub fn reset(&self) {
+        self.origin.store(UNKNOWN, Ordering::Release);
+    }
+
+    pub fn output_latency(&self) -> Option<Duration> {
+        self.known().map(|_| Duration::from_nanos(self.output_latency.load(Ordering::Relaxed)))
+    }
+
+    pub fn instant_for_frame(&self, frame: usize) -> Option<Instant> {
+        let (origin, sample_rate) = self.known()?;
+        let nanos = origin + (frame as f64 * 1e9 / sample_rate as f64) as i64;
+
+        if nanos >= 0 {
+            Some(self.epoch + Duration::from_nanos(nanos as u64))
+        } else {
+            self.epoch.checked_sub(Duration::from_nanos(nanos.unsigned_abs()))
+        }
+    }
+
+    // Returns None for instants before frame zero was played.
+    pub fn frame_for_instant(&self, instant: Instant) -> Option<usize> {
+        let (origin, sample_rate) = self.known()?;
+        let nanos = nanos_between(self.epoch, instant) - origin;
+
+        (nanos >= 0).then(|| (nanos as f64 * sample_rate as f64 / 1e9).round() as usize)
+    }
+
+    fn known(&self) -> Option<(i64, usize)> {
+        let origin = self.origin.load(Ordering::Acquire);
+        (origin != UNKNOWN).then(|| (origin, self.sample_rate.load(Ordering::Relaxed)))
+    }
+}
+
+fn nanos_between(from: Instant, to: Instant) -> i64 {
+    match to.checked_duration_since(from) {
+        Some(duration) => duration.as_nanos() as i64,
+        None => -(from.duration_since(to).as_nanos() as i64),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_maps_frames_to_the_instants_they_are_played() {
+        let clock = PlaybackClock::new();
+        assert_eq!(clock.instant_for_frame(0), None);
+
+        let now = clock.epoch + Duration::from_secs(10);
+        clock.update(100, 100, Duration::from_millis(20), now);
+
+        let played_at = now + Duration::from_millis(20);
+
+        assert_eq!(clock.instant_for_frame(100), Some(played_at));
+        assert_eq!(clock.instant_for_frame(150), Some(played_at + Duration::from_millis(500)));
+        assert_eq!(clock.frame_for_instant(played_at + Duration::from_secs(2)), Some(300));
+        assert_eq!(clock.output_latency(), Some(Duration::from_millis(20)));
+
+        clock.reset();
+        assert_eq!(clock.frame_for_instant(now), None);
+    }
+}
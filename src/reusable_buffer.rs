@@ -60,3 +60,17 @@ impl<S: MaybeDynamic<usize>> Iterator for ReusableBuffer<S> {
         (self.strategy)(self)
     }
 }
+
+impl<S: MaybeDynamic<usize>> BlockSource for ReusableBuffer<S> {
+    fn fill_block(&mut self, block: &mut [f32]) -> usize {
+        let position = if S::is_static() { self.counter } else { self.seek.get() };
+
+        let remaining = self.source.get(position..).unwrap_or(&[]);
+        let written = remaining.len().min(block.len());
+
+        block[..written].copy_from_slice(&remaining[..written]);
+        if S::is_static() { self.counter += written; } else { self.seek.add(written); }
+
+        written
+    }
+}
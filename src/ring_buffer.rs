@@ -0,0 +1,81 @@
+use crate::*;
+
+// A fixed-size queue of samples that one thread writes to and another reads
+// from without locking, e.g. to pass samples from an input device's callback
+// to wherever the input is consumed.
+//
+// The positions only ever increase and are wrapped when indexing so that the
+// buffer can tell the difference between being full and being empty.
+
+pub(crate) struct RingBuffer {
+    samples: Box<[AtomicF32]>,
+    read: AtomicUsize,
+    write: AtomicUsize,
+}
+
+impl RingBuffer {
+    pub fn new(capacity: usize) -> Self {
+        let samples = (0..capacity.max(1)).map(|_| AtomicF32::new(0.)).collect();
+
+        Self { samples, read: AtomicUsize::new(0), write: AtomicUsize::new(0) }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.write.load(Ordering::Acquire) - self.read.load(Ordering::Acquire)
+    }
+
+    // Writes as many whole frames as there is space for and drops the rest so
+    // that the reader never sees a partial frame. Returns the samples written.
+    pub fn push_frames<I: ExactSizeIterator<Item=f32>>(&self, samples: I, channels: usize) -> usize {
+        let write = self.write.load(Ordering::Relaxed);
+        let space = self.samples.len() - (write - self.read.load(Ordering::Acquire));
+
+        let amount = space.min(samples.len()) / channels * channels;
+
+        for (i, sample) in samples.take(amount).enumerate() {
+            self.samples[(write + i) % self.samples.len()].store(sample, Ordering::Relaxed);
+        }
+
+        self.write.store(write + amount, Ordering::Release);
+        amount
+    }
+
+    pub fn pop(&self) -> Option<f32> {
+        let read = self.read.load(Ordering::Relaxed);
+        if read == self.write.load(Ordering::Acquire) { return None; }
+
+        let sample = self.samples[read % self.samples.len()].load(Ordering::Relaxed);
+        self.read.store(read + 1, Ordering::Release);
+
+        Some(sample)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_reads_samples_in_the_order_they_were_written() {
+        let buffer = RingBuffer::new(4);
+
+        buffer.push_frames([1., 2., 3.].into_iter(), 1);
+        assert_eq!(buffer.pop(), Some(1.));
+        assert_eq!(buffer.pop(), Some(2.));
+
+        buffer.push_frames([4., 5., 6.].into_iter(), 1);
+        assert_eq!(std::iter::from_fn(|| buffer.pop()).collect::<Vec<_>>(), vec![3., 4., 5., 6.]);
+    }
+
+    #[test]
+    fn it_drops_whole_frames_that_do_not_fit() {
+        let buffer = RingBuffer::new(5);
+
+        assert_eq!(buffer.push_frames([1., 2., 3., 4., 5., 6.].into_iter(), 2), 4);
+        assert_eq!(buffer.len(), 4);
+    }
+}
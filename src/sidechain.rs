@@ -0,0 +1,102 @@
+use crate::*;
+
+// Passes a source through unchanged and publishes its level to a DynamicFloat
+// so that it can control another source, e.g. dialogue ducking music with Duck.
+// To use a whole bus as the sidechain, put this in the bus's effect chain.
+//
+// The level is the peak of the most recent CHUNK samples. It is published in
+// chunks so that reading it on every frame isn't noisy and it drops to zero
+// when the source finishes, or is stopped or removed before it finishes, so
+// that anything it is controlling is released.
+
+pub struct Sidechain<S: Iterator<Item=f32>> {
+    level: DynamicFloat,
+    source: S,
+    peak: f32,
+    count: usize,
+}
+
+const CHUNK: usize = 64;
+
+impl<S: Iterator<Item=f32>> Sidechain<S> {
+    pub fn new(level: DynamicFloat, source: S) -> Self {
+        Self { level, source, peak: 0., count: 0 }
+    }
+
+    fn process(&mut self, sample: f32) {
+        self.peak = self.peak.max(sample.abs());
+        self.count += 1;
+
+        if self.count == CHUNK {
+            self.level.set(self.peak);
+            (self.peak, self.count) = (0., 0);
+        }
+    }
+}
+
+impl<S: Iterator<Item=f32>> Iterator for Sidechain<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = self.source.next();
+
+        match sample {
+            Some(s) => self.process(s),
+            None => self.level.set(0.),
+        }
+
+        sample
+    }
+}
+
+impl<S: Iterator<Item=f32> + BlockSource> BlockSource for Sidechain<S> {
+    fn fill_block(&mut self, block: &mut [f32]) -> usize {
+        let written = self.source.fill_block(block);
+
+        block[..written].iter().for_each(|s| self.process(*s));
+        if written < block.len() { self.level.set(0.); }
+
+        written
+    }
+}
+
+impl<S: Iterator<Item=f32>> Drop for Sidechain<S> {
+    fn drop(&mut self) {
+        self.level.set(0.);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_publishes_the_peak_of_each_chunk_and_zero_when_finished() {
+        let level = DynamicFloat::default();
+        let samples = (0..CHUNK * 2).map(|i| if i == 10 { -0.8 } else { 0.1 });
+        let mut sidechain = Sidechain::new(level.clone(), samples);
+
+        sidechain.by_ref().take(CHUNK).for_each(drop);
+        assert_eq!(level.get(), 0.8);
+
+        sidechain.by_ref().take(CHUNK).for_each(drop);
+        assert_eq!(level.get(), 0.1);
+
+        assert_eq!(sidechain.next(), None);
+        assert_eq!(level.get(), 0.);
+    }
+
+    #[test]
+    fn it_publishes_zero_when_the_source_is_removed_via_its_handle() {
+        let mixer = AudioMixer::offline(1, 44100);
+        let level = DynamicFloat::default();
+
+        let handle = mixer.add(Sidechain::new(level.clone(), std::iter::repeat(0.5)));
+        mixer.render_frames(CHUNK);
+        assert_eq!(level.get(), 0.5);
+
+        handle.remove();
+        mixer.render_frames(1);
+        assert_eq!(level.get(), 0.);
+    }
+}
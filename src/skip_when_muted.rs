@@ -81,3 +81,9 @@ impl<V: MaybeDynamic<f32>, S: Iterator<Item=f32>> Iterator for SkipWhenMuted<V,
         (self.strategy)(self)
     }
 }
+
+impl<V: MaybeDynamic<f32>, S: Iterator<Item=f32>> BlockSource for SkipWhenMuted<V, S> {
+    fn fill_block(&mut self, block: &mut [f32]) -> usize {
+        fill_from_iterator(self, block)
+    }
+}
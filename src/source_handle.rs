@@ -0,0 +1,99 @@
+use crate::*;
+use crate::audio_mixer::Command;
+use crate::waiting::{Condition, Notifier};
+
+// Returned from AudioMixer::add so that the source can be referred to again
+// once it has been handed over to the mixer. Handles are cheap to clone.
+
+#[derive(Clone)]
+pub struct SourceHandle {
+    id: usize,
+    state: Arc<SourceState>,
+    commands: Sender<Command>,
+    notifier: Arc<Notifier>,
+}
+
+#[derive(Default)]
+pub(crate) struct SourceState {
+    stopped: AtomicBool,
+    finished: AtomicBool,
+    frames_played: AtomicUsize,
+}
+
+impl SourceHandle {
+    pub(crate) fn new(id: usize, state: Arc<SourceState>, commands: Sender<Command>, notifier: Arc<Notifier>) -> Self {
+        Self { id, state, commands, notifier }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    // The source stops at the next frame boundary the next time the mixer runs.
+    pub fn stop(&self) {
+        self.state.stopped.store(true, Ordering::Relaxed);
+    }
+
+    // The source is removed before the mixer produces its next block of samples,
+    // even if that isn't on a frame boundary, and is reported as no longer
+    // playing as soon as this method returns.
+    pub fn remove(&self) {
+        self.stop();
+        self.state.finish();
+
+        let _ = self.commands.send(Command::Remove(self.id));
+    }
+
+    // Sources that haven't started yet are considered to be playing.
+    pub fn is_playing(&self) -> bool {
+        !self.state.is_finished()
+    }
+
+    // Blocks until the source finishes or is stopped or removed.
+    pub fn wait(&self) {
+        self.notifier.wait(&self.commands, &self.finished(), None);
+    }
+
+    // Returns false if the source was still playing when the timeout expired.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        self.notifier.wait(&self.commands, &self.finished(), Some(timeout))
+    }
+
+    pub fn wait_async(&self) -> WaitFuture {
+        self.notifier.wait_future(&self.commands, self.finished())
+    }
+
+    pub fn frames_played(&self) -> usize {
+        self.state.frames_played.load(Ordering::Relaxed)
+    }
+}
+
+impl SourceHandle {
+    fn finished(&self) -> Condition {
+        Condition::SourceFinished(self.state.clone())
+    }
+}
+
+impl SourceState {
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Relaxed)
+    }
+
+    pub fn finish(&self) {
+        self.finished.store(true, Ordering::Relaxed);
+    }
+
+    pub fn set_frames_played(&self, frames: usize) {
+        self.frames_played.store(frames, Ordering::Relaxed);
+    }
+}
+
+impl std::fmt::Debug for SourceHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SourceHandle").field("id", &self.id).finish()
+    }
+}
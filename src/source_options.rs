@@ -0,0 +1,17 @@
+use crate::*;
+
+// Options that control how a source is played by AudioMixer::add_with. Fields
+// that aren't set use their defaults, e.g. SourceOptions { bus, ..Default::default() }.
+
+#[derive(Clone, Debug, Default)]
+pub struct SourceOptions {
+    // The bus to route the source into. It plays straight to the output if None.
+    pub bus: Option<Bus>,
+
+    // The output frame to start playing the source at. See AudioMixer::add_at.
+    pub start_frame: Option<usize>,
+
+    // Sources with a higher priority are stolen last when the mixer has a
+    // VoiceLimit with StealingPolicy::LowestPriority. The default is 0.
+    pub priority: i32,
+}
@@ -43,3 +43,9 @@ impl<V: MaybeDynamic<f32>, S: Iterator<Item=f32>> Iterator for StopWhenMuted<V,
         (self.strategy)(self)
     }
 }
+
+impl<V: MaybeDynamic<f32>, S: Iterator<Item=f32>> BlockSource for StopWhenMuted<V, S> {
+    fn fill_block(&mut self, block: &mut [f32]) -> usize {
+        fill_from_iterator(self, block)
+    }
+}
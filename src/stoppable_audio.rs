@@ -44,3 +44,22 @@ impl<X: MaybeDynamic<bool>, S: Iterator<Item=f32>> Iterator for StoppableAudio<X
         (self.strategy)(self)
     }
 }
+
+// The stopped control is only checked once per block when processing blocks.
+impl<X: MaybeDynamic<bool>, S: Iterator<Item=f32> + BlockSource> BlockSource for StoppableAudio<X, S> {
+    fn fill_block(&mut self, block: &mut [f32]) -> usize {
+        if self.stopped.get() {
+            self.strategy = Self::always_emit_none;
+            return 0;
+        }
+
+        let written = self.source.fill_block(block);
+
+        if written < block.len() {
+            self.stopped.set(true);
+            self.strategy = Self::always_emit_none;
+        }
+
+        written
+    }
+}
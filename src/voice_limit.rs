@@ -0,0 +1,36 @@
+use crate::*;
+
+// Limits how many sources the mixer plays at once so that bursts of sounds
+// don't overload the audio thread. When a source starts and the limit has been
+// reached, another source is chosen by the policy and faded out. It is then
+// reported with a MixerEvent::Stolen and its handle stops playing. Voices that
+// are fading out count towards max_voices until they're silent.
+
+#[derive(Clone, Debug)]
+pub struct VoiceLimit {
+    pub max_voices: usize,
+    pub policy: StealingPolicy,
+    pub fade_out: Duration,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StealingPolicy {
+    // Steal the source that has been playing the longest.
+    #[default]
+    Oldest,
+
+    // Steal the source whose last block was the quietest. Sources that have
+    // only just started can't be chosen because they haven't been measured yet.
+    Quietest,
+
+    // Steal the source with the lowest SourceOptions::priority, or the oldest
+    // of those if there's a tie. If the starting source's priority is lower
+    // than all of the playing sources, it is the one that doesn't play.
+    LowestPriority,
+}
+
+impl Default for VoiceLimit {
+    fn default() -> Self {
+        Self { max_voices: 32, policy: StealingPolicy::default(), fade_out: Duration::from_millis(10) }
+    }
+}
@@ -0,0 +1,180 @@
+use crate::*;
+use crate::audio_mixer::{Command, Status};
+use crate::mixer_event::event_channel;
+use crate::source_handle::SourceState;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Condvar, Once};
+use std::task::{Context, Poll, Waker};
+
+// Wakes up threads and futures that are waiting for sources to finish. The
+// audio thread can't wake them itself without locking so this subscribes to
+// the mixer's events and a thread of its own does the waking instead. That
+// thread isn't started until something waits for the first time.
+
+pub(crate) struct Notifier {
+    wakers: Mutex<Vec<Waker>>,
+    condvar: Condvar,
+    started: Once,
+}
+
+#[derive(Clone)]
+pub(crate) enum Condition {
+    MixerIdle(Arc<Status>),
+    SourceFinished(Arc<SourceState>),
+}
+
+// Returned from AudioMixer::wait_async and SourceHandle::wait_async. It works
+// with any executor and completes when all sources (or the one source) finish.
+pub struct WaitFuture {
+    notifier: Arc<Notifier>,
+    condition: Condition,
+}
+
+impl Notifier {
+    pub fn new() -> Self {
+        Self { wakers: Mutex::default(), condvar: Condvar::new(), started: Once::new() }
+    }
+
+    // Returns whether the condition was met before the timeout (if any) expired.
+    pub fn wait(self: &Arc<Self>, commands: &Sender<Command>, condition: &Condition, timeout: Option<Duration>) -> bool {
+        self.start(commands);
+
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let mut wakers = self.wakers.lock().unwrap();
+
+        while !condition.is_met() {
+            wakers = match deadline {
+                None => self.condvar.wait(wakers).unwrap(),
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() { return false; }
+
+                    self.condvar.wait_timeout(wakers, remaining).unwrap().0
+                },
+            };
+        }
+
+        true
+    }
+
+    pub fn wait_future(self: &Arc<Self>, commands: &Sender<Command>, condition: Condition) -> WaitFuture {
+        self.start(commands);
+
+        WaitFuture { notifier: self.clone(), condition }
+    }
+
+    fn start(self: &Arc<Self>, commands: &Sender<Command>) {
+        self.started.call_once(|| {
+            let (sender, receiver) = event_channel();
+            let _ = commands.send(Command::Subscribe(sender));
+
+            let notifier = self.clone();
+
+            // The thread exits when the mixer is dropped and stops sending events.
+            std::thread::spawn(move || {
+                for event in receiver {
+                    if !matches!(event, MixerEvent::Started { .. }) { notifier.notify(); }
+                }
+            });
+        });
+    }
+
+    // Taking the lock before notifying means a waiter can't miss the wake up
+    // between checking its condition and starting to wait.
+    fn notify(&self) {
+        let wakers = std::mem::take(&mut *self.wakers.lock().unwrap());
+        self.condvar.notify_all();
+
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+impl Condition {
+    fn is_met(&self) -> bool {
+        match self {
+            Self::MixerIdle(status) => !status.is_playing(),
+            Self::SourceFinished(state) => state.is_finished(),
+        }
+    }
+}
+
+impl Future for WaitFuture {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        if self.condition.is_met() { return Poll::Ready(()); }
+
+        let mut wakers = self.notifier.wakers.lock().unwrap();
+
+        // Check again now that the lock is held in case the notifier ran between.
+        if self.condition.is_met() { return Poll::Ready(()); }
+
+        if !wakers.iter().any(|w| w.will_wake(context.waker())) { wakers.push(context.waker().clone()); }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::task::Wake;
+
+    struct ThreadWaker(std::thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+        let mut context = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) { return output; }
+            std::thread::park();
+        }
+    }
+
+    #[test]
+    fn it_times_out_if_sources_are_still_playing() {
+        let mixer = AudioMixer::offline(1, 44100);
+        let handle = mixer.add([1., 2.].into_iter());
+
+        assert!(!mixer.wait_timeout(Duration::from_millis(10)));
+        assert!(!handle.wait_timeout(Duration::from_millis(10)));
+
+        mixer.render_frames(3);
+        assert!(mixer.wait_timeout(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn it_wakes_threads_that_are_waiting_for_a_source_to_finish() {
+        let mixer = AudioMixer::offline(1, 44100);
+        let handle = mixer.add([1., 2.].into_iter());
+
+        let waiting = handle.clone();
+        let thread = std::thread::spawn(move || waiting.wait_timeout(Duration::from_secs(10)));
+
+        mixer.render_frames(3);
+        assert!(thread.join().unwrap());
+    }
+
+    #[test]
+    fn it_wakes_futures_when_all_sources_have_finished() {
+        let mixer = AudioMixer::offline(1, 44100);
+        mixer.add([1., 2.].into_iter());
+
+        let future = mixer.wait_async();
+        let thread = std::thread::spawn(move || block_on(future));
+
+        mixer.render_frames(1);
+        assert!(!thread.is_finished());
+
+        mixer.render_frames(2);
+        thread.join().unwrap();
+    }
+}